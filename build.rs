@@ -0,0 +1,46 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Record build information for `--version --verbose`.
+
+use std::process::Command;
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=HOME_GIT_COMMIT={}", commit.trim());
+    }
+    // Keep builds reproducible: Use the date from SOURCE_DATE_EPOCH, see
+    // <https://reproducible-builds.org/specs/source-date-epoch/>, or else the
+    // date of the commit.
+    let date = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse::<i64>().ok())
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .or_else(|| {
+            Command::new("git")
+                .args(["log", "-1", "--format=%cs"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|date| date.trim().to_string())
+                .filter(|date| !date.is_empty())
+        });
+    if let Some(date) = date {
+        println!("cargo:rustc-env=HOME_BUILD_DATE={}", date);
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -4,10 +4,14 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{future::Future, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    future::Future,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{Context, Result};
-use chrono::{DateTime, Duration, Utc};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDateTime, NaiveTime, Timelike, Utc};
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, event, info_span, instrument, Level};
@@ -15,32 +19,511 @@ use tracing_futures::Instrument;
 
 use crate::{
     config::{Config, DesiredConnection},
-    mvg::{Connection, TransportType},
+    mvg::{Connection, Place, Station, TransportType},
 };
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+/// The version of the cache format.
+///
+/// Increment whenever the cache format changes incompatibly, and add a
+/// migration from the previous layout to [`ConnectionsCache::from_slice`].
+const CACHE_VERSION: u32 = 1;
+
+/// The least number of cached connections of a route to use them instead of refreshing the route.
+///
+/// See [`ConnectionsCache::evict_too_few_connections`].
+pub const MIN_CACHED_CONNECTIONS: usize = 3;
+
+/// Whether `connections` contain a connection with the same signature as `connection`.
+fn contains_signature(connections: &[Connection], connection: &Connection) -> bool {
+    let signature = connection.signature();
+    connections.iter().any(|c| c.signature() == signature)
+}
+
+/// Prefer connections with fewer transfers among connections which start within `window`.
+///
+/// Order `connections` by start time, and group them into windows which begin
+/// at the first connection not yet grouped.  Within every window order
+/// connections by the number of transfers, and finally by start time.
+pub fn prefer_direct_connections(
+    connections: &mut [(&DesiredConnection, &Connection)],
+    window: Duration,
+) {
+    connections.sort_by_key(|(desired, c)| leave_time(desired, c));
+    let mut begin = 0;
+    while begin < connections.len() {
+        let (desired, c) = connections[begin];
+        let window_end = leave_time(desired, c) + window;
+        let end = begin
+            + 1
+            + connections[begin + 1..]
+                .iter()
+                .take_while(|(desired, c)| leave_time(desired, c) < window_end)
+                .count();
+        connections[begin..end].sort_by_key(|(desired, c)| (c.transfers(), leave_time(desired, c)));
+        begin = end;
+    }
+}
+
+/// Order connections which I can leave for latest first.
+///
+/// Reverse the order of [`ConnectionsCache::all_connections`].
+pub fn latest_leave_first(connections: &mut [(&DesiredConnection, &Connection)]) {
+    connections.sort_by_key(|(desired, c)| std::cmp::Reverse(order_key(desired, c)));
+}
+
+/// Order connections which I actually have to leave for first first.
+///
+/// Unlike [`ConnectionsCache::all_connections`] account for departure
+/// delays, so that a late connection comes after a later connection which
+/// leaves earlier.
+pub fn actual_leave_first(connections: &mut [(&DesiredConnection, &Connection)]) {
+    connections.sort_by_key(|(desired, c)| {
+        (
+            c.actual_departure_time() - desired.walk_to_start,
+            c.actual_arrival_time(),
+        )
+    });
+}
+
+/// Group `connections` by the local hour of their planned departure.
+///
+/// Keep the order of connections within every hour.
+pub fn by_departure_hour<'a>(
+    connections: &[(&'a DesiredConnection, &'a Connection)],
+) -> BTreeMap<NaiveDateTime, Vec<(&'a DesiredConnection, &'a Connection)>> {
+    let mut hours: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for (desired, connection) in connections {
+        let departure = connection
+            .planned_departure_time()
+            .with_timezone(&Local)
+            .naive_local();
+        let hour =
+            departure.date().and_time(NaiveTime::MIN) + Duration::hours(departure.hour().into());
+        hours.entry(hour).or_default().push((*desired, *connection));
+    }
+    hours
+}
+
+/// When to leave for `connection` of the `desired` route.
+fn leave_time(desired: &DesiredConnection, connection: &Connection) -> DateTime<FixedOffset> {
+    connection.planned_departure_time() - desired.walk_to_start
+}
+
+/// The key to order connections by.
+///
+/// Order by leave time, and then by arrival, so that connections which
+/// leave at the same time come in the same order, regardless of the order
+/// the MVG API returned them in.
+fn order_key(
+    desired: &DesiredConnection,
+    connection: &Connection,
+) -> (DateTime<FixedOffset>, DateTime<FixedOffset>) {
+    (
+        leave_time(desired, connection),
+        connection.planned_arrival_time(),
+    )
+}
+
+/// Whether `connection` departs at `time`.
+///
+/// Compare the local hour and minute of the planned departure time.
+pub fn departs_at(connection: &Connection, time: NaiveTime) -> bool {
+    let departure = connection
+        .planned_departure_time()
+        .with_timezone(&Local)
+        .time();
+    time.hour() == departure.hour() && time.minute() == departure.minute()
+}
+
+/// The first point in time at or after `after` whose local time is `time`.
+///
+/// Skip days on which `time` does not exist, e.g. because of a daylight
+/// saving transition, and take the earlier point in time if `time` is
+/// ambiguous.
+pub fn next_local_time(after: DateTime<Local>, time: NaiveTime) -> DateTime<Local> {
+    after
+        .date_naive()
+        .iter_days()
+        .filter_map(|day| day.and_time(time).and_local_timezone(Local).earliest())
+        .find(|candidate| after <= *candidate)
+        .expect("Local time should exist on some day")
+}
+
+/// Whether `connection` departs at one of the preferred times of `desired`.
+pub fn is_preferred_connection(desired: &DesiredConnection, connection: &Connection) -> bool {
+    desired
+        .preferred_times
+        .iter()
+        .any(|t| departs_at(connection, *t))
+}
+
+/// A key which identifies `connection` of the `desired` route across runs.
+pub fn connection_key(desired: &DesiredConnection, connection: &Connection) -> String {
+    format!("{} {}", desired.id(), connection.signature())
+}
+
+/// A line `connection` rides, along with the stop and time it departs at.
+type Ride<'a> = (&'a str, &'a str, DateTime<FixedOffset>);
+
+/// The first and the last line `connection` rides, and the arrival time.
+///
+/// `None` if `connection` only walks.
+fn ride_key(connection: &Connection) -> Option<(Ride<'_>, Ride<'_>, DateTime<FixedOffset>)> {
+    let mut rides = connection
+        .parts
+        .iter()
+        .filter(|p| p.line_transport_type() != TransportType::Pedestrian)
+        .map(|ride| {
+            (
+                ride.line_label(),
+                ride.from().name(),
+                ride.from().planned_departure(),
+            )
+        });
+    let first = rides.next()?;
+    let last = rides.next_back().unwrap_or(first);
+    Some((first, last, connection.planned_arrival_time()))
+}
+
+/// The time `connection` walks before its first ride.
+fn initial_walking_time(connection: &Connection) -> Duration {
+    connection
+        .parts
+        .iter()
+        .take_while(|p| p.line_transport_type() == TransportType::Pedestrian)
+        .map(|p| p.to().planned_arrival() - p.from().planned_departure())
+        .fold(Duration::zero(), |total, walk| total + walk)
+}
+
+/// Why a connection was evicted from the cache.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EvictionReason {
+    /// The connection can no longer be reached.
+    Unreachable,
+    /// Its route had too few connections left.
+    TooFew,
+    /// The connection starts with walking.
+    PedestrianStart,
+    /// The connection walks to an adjacent stop to catch a line it could catch at the start as well.
+    AdjacentStopWalk,
+    /// Its route is refreshed as requested.
+    Refresh,
+}
+
+impl std::fmt::Display for EvictionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            EvictionReason::Unreachable => "unreachable",
+            EvictionReason::TooFew => "too-few",
+            EvictionReason::PedestrianStart => "pedestrian-start",
+            EvictionReason::AdjacentStopWalk => "adjacent-stop-walk",
+            EvictionReason::Refresh => "refresh",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Log that `connection` of the `desired` route was evicted for `reason`.
+fn log_eviction(desired: &DesiredConnection, connection: &Connection, reason: EvictionReason) {
+    event!(
+        Level::DEBUG,
+        route = %desired.id(),
+        departure = %connection.planned_departure_time(),
+        line = %connection.departure().line_label(),
+        transport_type = %connection.departure().line_transport_type(),
+        reason = %reason,
+        "Evicted connection departing at {} with {} {}: {}",
+        connection.planned_departure_time().format("%H:%M"),
+        connection.departure().line_transport_type(),
+        connection.departure().line_label(),
+        reason
+    );
+}
+
+/// Keep only connections of the `desired` route matching `predicate`; log others as evicted for `reason`.
+fn retain_connections<P>(
+    desired: &DesiredConnection,
+    connections: Vec<Connection>,
+    reason: EvictionReason,
+    predicate: P,
+) -> Vec<Connection>
+where
+    P: Fn(&Connection) -> bool,
+{
+    connections
+        .into_iter()
+        .filter(|c| {
+            let keep = predicate(c);
+            if !keep {
+                log_eviction(desired, c, reason);
+            }
+            keep
+        })
+        .collect()
+}
+
+/// Log every connection of the `desired` route as evicted for `reason`, and return no connections.
+fn evict_connections(
+    desired: &DesiredConnection,
+    connections: Vec<Connection>,
+    reason: EvictionReason,
+) -> Vec<Connection> {
+    for connection in &connections {
+        log_eviction(desired, connection, reason);
+    }
+    Vec::new()
+}
+
+/// How much of the time to walk to the start must remain to reach a connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reachability {
+    /// At least half of the time to walk to the start must remain.
+    Lenient,
+    /// All of the time to walk to the start must remain.
+    Strict,
+}
+
+impl Reachability {
+    /// How much of `walk_to_start` must remain.
+    fn required_walk_time(self, walk_to_start: Duration) -> Duration {
+        match self {
+            Reachability::Lenient => walk_to_start / 2,
+            Reachability::Strict => walk_to_start,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ConnectionsCache {
+    pub version: u32,
     pub connections: Vec<(DesiredConnection, Vec<Connection>)>,
+    /// Keys of connections shown by the last run, see [`connection_key`].
+    #[serde(default)]
+    pub shown: Vec<String>,
+    /// The name of the start station resolved for every route, by [`DesiredConnection::id`].
+    #[serde(default)]
+    pub start_stations: HashMap<String, String>,
+    /// The start time connections of every route were requested for, to the minute, by [`DesiredConnection::id`].
+    #[serde(default)]
+    pub start_times: HashMap<String, DateTime<Utc>>,
 }
 
-impl ConnectionsCache {
-    fn cache_path() -> PathBuf {
-        dirs::cache_dir()
-            .expect("cache directory missing")
-            .join("de.swsnr.home")
-            .join("connections")
+impl Default for ConnectionsCache {
+    fn default() -> Self {
+        Self {
+            version: CACHE_VERSION,
+            connections: Vec::new(),
+            shown: Vec::new(),
+            start_stations: HashMap::new(),
+            start_times: HashMap::new(),
+        }
     }
+}
+
+/// Truncate `time` to the minute.
+fn truncate_to_minute(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.with_second(0)
+        .and_then(|time| time.with_nanosecond(0))
+        .unwrap_or(time)
+}
+
+/// Just the version of a serialized cache.
+#[derive(Debug, Deserialize)]
+struct CacheVersion {
+    /// The version of the cache; caches before version 1 had no version field.
+    #[serde(default)]
+    version: u32,
+}
+
+/// The cache layout before versioning.
+#[derive(Debug, Deserialize)]
+struct ConnectionsCacheV0 {
+    connections: Vec<(DesiredConnection, Vec<Connection>)>,
+}
 
-    pub fn load() -> Result<Self> {
-        let path = Self::cache_path();
-        let contents = std::fs::read(&path)
+impl From<ConnectionsCacheV0> for ConnectionsCache {
+    fn from(cache: ConnectionsCacheV0) -> Self {
+        Self {
+            connections: cache.connections,
+            ..Self::default()
+        }
+    }
+}
+
+/// The directory for cache files, i.e. `$XDG_CACHE_HOME/de.swsnr.home`.
+pub fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .expect("cache directory missing")
+        .join("de.swsnr.home")
+}
+
+/// The path of the cache file for the given configuration files.
+///
+/// The default configuration uses `connections` in [`cache_dir`].  Other
+/// configuration files use `connections-HASH`, where `HASH` is a hash of
+/// their absolute paths, so that different configurations do not replace
+/// each other's cache.
+pub fn cache_path(config_files: &[PathBuf]) -> PathBuf {
+    if config_files.is_empty() {
+        cache_dir().join("connections")
+    } else {
+        // FNV-1a, which unlike the hasher of std is stable across releases
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for file in config_files {
+            let file = std::path::absolute(file).unwrap_or_else(|_| file.clone());
+            for byte in file.as_os_str().as_encoded_bytes().iter().chain(&[0]) {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        cache_dir().join(format!("connections-{:016x}", hash))
+    }
+}
+
+/// The path of the snapshot of the last shown connections for the cache at `cache_path`.
+///
+/// Append `-last-good` to the file name, before the extension, so that the
+/// snapshot uses the same format as the cache.
+pub fn last_good_path(cache_path: &Path) -> PathBuf {
+    let mut file_name = cache_path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push("-last-good");
+    if let Some(extension) = cache_path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    cache_path.with_file_name(file_name)
+}
+
+/// Whether the file at `path` is a snapshot of the last shown connections, see [`last_good_path`].
+fn is_last_good_snapshot(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem.ends_with("-last-good"))
+}
+
+/// All cache files in [`cache_dir`].
+///
+/// Include JSON caches, but not snapshots of the last shown connections.
+pub fn cache_files() -> Result<Vec<PathBuf>> {
+    cache_files_in(&cache_dir())
+}
+
+/// All cache files in `directory`, see [`cache_files`].
+fn cache_files_in(directory: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Failed to read cache directory {}", directory.display()))
+        }
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("Failed to read cache directory {}", directory.display()))?
+            .path();
+        let is_cache_file = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name == "connections" || name.starts_with("connections-"));
+        if is_cache_file && !is_last_good_snapshot(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Remove cache files in [`cache_dir`] which fail to deserialize.
+///
+/// Return the paths and sizes of removed files.
+pub fn prune_cache_files() -> Result<Vec<(PathBuf, u64)>> {
+    prune_cache_files_in(&cache_dir())
+}
+
+/// Remove cache files in `directory` which fail to deserialize, see [`prune_cache_files`].
+fn prune_cache_files_in(directory: &Path) -> Result<Vec<(PathBuf, u64)>> {
+    let mut removed = Vec::new();
+    for path in cache_files_in(directory)? {
+        let size = std::fs::metadata(&path)
+            .with_context(|| format!("Failed to read cache file at {}", path.display()))?
+            .len();
+        // Load the cache to read JSON caches as JSON
+        if let Err(error) = ConnectionsCache::load(&path) {
+            debug!(
+                "Removing unusable cache file {}: {:#}",
+                path.display(),
+                error
+            );
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove cache file at {}", path.display()))?;
+            removed.push((path, size));
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether the cache file at `path` uses JSON, according to its extension.
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+impl ConnectionsCache {
+    /// Load the cache from the file at `path`.
+    ///
+    /// Read JSON if `path` has a `.json` extension, see [`Self::from_json`],
+    /// and flexbuffers otherwise.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)
             .with_context(|| format!("Failed to read cache file at {}", path.display()))?;
-        flexbuffers::from_slice(&contents)
-            .with_context(|| format!("Failed to deserialize cache from {}", path.display()))
+        if is_json(path) {
+            Self::from_json(&contents)
+        } else {
+            Self::from_slice(&contents)
+        }
+        .with_context(|| format!("Failed to deserialize cache from {}", path.display()))
+    }
+
+    /// Deserialize a cache from JSON `contents`.
+    ///
+    /// Unlike [`Self::from_slice`] do not migrate older versions, and fail
+    /// unless the cache has the current version.
+    pub fn from_json(contents: &[u8]) -> Result<Self> {
+        let CacheVersion { version } = serde_json::from_slice(contents)?;
+        if version == CACHE_VERSION {
+            Ok(serde_json::from_slice(contents)?)
+        } else {
+            Err(anyhow!("Unknown cache version {}", version))
+        }
+    }
+
+    /// Deserialize a cache from `contents`.
+    ///
+    /// Migrate caches of older known versions to the current version; fail
+    /// if the version of the cache is unknown.
+    pub fn from_slice(contents: &[u8]) -> Result<Self> {
+        let CacheVersion { version } = flexbuffers::from_slice(contents)?;
+        match version {
+            CACHE_VERSION => Ok(flexbuffers::from_slice(contents)?),
+            0 => {
+                event!(
+                    Level::INFO,
+                    "Migrating cache from version 0 to version {}",
+                    CACHE_VERSION
+                );
+                let cache: ConnectionsCacheV0 = flexbuffers::from_slice(contents)?;
+                Ok(cache.into())
+            }
+            _ => Err(anyhow!("Unknown cache version {}", version)),
+        }
     }
 
-    pub fn save(&self) -> Result<()> {
-        let cache_file = Self::cache_path();
+    /// Save the cache to the file at `cache_file`.
+    ///
+    /// Write JSON if `cache_file` has a `.json` extension, and flexbuffers otherwise.
+    pub fn save(&self, cache_file: &Path) -> Result<()> {
         let cache_dir = cache_file
             .parent()
             .expect("Cache path should not be a file system root!");
@@ -50,39 +533,267 @@ impl ConnectionsCache {
                 cache_dir.display()
             )
         })?;
-        let contents = flexbuffers::to_vec(self)
-            .with_context(|| "Failed to serialize connection cache".to_string())?;
-        std::fs::write(&cache_file, contents)
+        let contents = if is_json(cache_file) {
+            serde_json::to_vec_pretty(self).map_err(anyhow::Error::from)
+        } else {
+            flexbuffers::to_vec(self).map_err(anyhow::Error::from)
+        }
+        .with_context(|| "Failed to serialize connection cache".to_string())?;
+        std::fs::write(cache_file, contents)
             .with_context(|| format!("Failed to write cache to {}", cache_file.display()))
     }
 
     /// Update the cache with the config `config`.
     ///
-    /// If the desired connections in `config` do not match the cached ones,
-    /// discard the entire cache and use the desired connections from `config`.
-    ///
-    /// Otherwise return this cache as is.
+    /// Use the desired connections from `config`, in the order of `config`.
+    /// Keep cached connections of every route whose [`DesiredConnection::id`]
+    /// is cached with exactly the same settings, and discard the rest.
     #[instrument(skip_all)]
     pub fn update_config(self, config: Config) -> Self {
-        if config
+        let mut cached = self
+            .connections
+            .into_iter()
+            .map(|(desired, connections)| (desired.id(), (desired, connections)))
+            .collect::<HashMap<_, _>>();
+        let connections = config
+            .connections
+            .into_iter()
+            .map(|desired| match cached.remove(&desired.id()) {
+                Some((cached_desired, connections)) if cached_desired == desired => {
+                    (desired, connections)
+                }
+                _ => {
+                    event!(
+                        Level::INFO,
+                        "Discarding cached connections from {} to {}, configuration changed",
+                        desired.start,
+                        desired.destination
+                    );
+                    (desired, Vec::new())
+                }
+            })
+            .collect();
+        Self {
+            connections,
+            ..self
+        }
+    }
+
+    /// Drop cached data of routes which are no longer in the cache.
+    ///
+    /// [`Self::update_config`] drops routes which are no longer configured,
+    /// but leaves their start stations and shown connections behind.
+    #[instrument(skip_all)]
+    pub fn compact(self) -> Self {
+        let ids = self
             .connections
             .iter()
-            .eq(self.connections.iter().map(|c| &c.0))
-        {
-            self
-        } else {
-            event!(
-                Level::INFO,
-                "Discarding cached connections, configuration changed"
-            );
-            Self {
-                connections: config
-                    .connections
-                    .into_iter()
-                    .map(|c| (c, Vec::new()))
-                    .collect(),
+            .map(|(desired, _)| desired.id())
+            .collect::<Vec<_>>();
+        let start_stations = self
+            .start_stations
+            .into_iter()
+            .filter(|(id, _)| ids.contains(id))
+            .collect::<HashMap<_, _>>();
+        let start_times = self
+            .start_times
+            .into_iter()
+            .filter(|(id, _)| ids.contains(id))
+            .collect::<HashMap<_, _>>();
+        // Keys of shown connections start with the ID of their route, see connection_key
+        let shown = self
+            .shown
+            .into_iter()
+            .filter(|key| ids.iter().any(|id| key.starts_with(&format!("{} ", id))))
+            .collect::<Vec<_>>();
+        Self {
+            start_stations,
+            start_times,
+            shown,
+            ..self
+        }
+    }
+
+    /// Merge `other` into this cache.
+    ///
+    /// Take routes of both caches, by [`DesiredConnection::id`].  For routes
+    /// in both caches, take the route from `other` and merge the connections
+    /// of both caches, ordered by planned departure.  Of connections with the
+    /// same [`Connection::signature`] take the one from `other`.  Start
+    /// stations from `other` take precedence as well.
+    pub fn merge(self, other: ConnectionsCache) -> Self {
+        let mut connections = self.connections;
+        for (desired, other_connections) in other.connections {
+            match connections.iter_mut().find(|(d, _)| d.id() == desired.id()) {
+                Some((existing, existing_connections)) => {
+                    let previous = std::mem::take(existing_connections)
+                        .into_iter()
+                        .filter(|c| !contains_signature(&other_connections, c))
+                        .collect::<Vec<_>>();
+                    let mut merged = other_connections;
+                    merged.extend(previous);
+                    merged.sort_by_key(|c| c.planned_departure_time());
+                    *existing = desired;
+                    *existing_connections = merged;
+                }
+                None => connections.push((desired, other_connections)),
+            }
+        }
+        let mut start_stations = self.start_stations;
+        start_stations.extend(other.start_stations);
+        let mut start_times = self.start_times;
+        start_times.extend(other.start_times);
+        let mut shown = self.shown;
+        for key in other.shown {
+            if !shown.contains(&key) {
+                shown.push(key);
             }
         }
+        Self {
+            connections,
+            shown,
+            start_stations,
+            start_times,
+            ..self
+        }
+    }
+
+    /// Fill routes without connections from the given `snapshot`.
+    ///
+    /// Take connections and start station of every route without connections
+    /// from the same route in `snapshot`, if the route has exactly the same
+    /// settings in `snapshot`.
+    #[instrument(skip_all)]
+    pub fn fill_empty_from(self, snapshot: ConnectionsCache) -> Self {
+        let mut snapshot_connections = snapshot.connections;
+        let snapshot_start_stations = snapshot.start_stations;
+        let mut start_stations = self.start_stations;
+        let connections = self
+            .connections
+            .into_iter()
+            .map(|(desired, connections)| {
+                if !connections.is_empty() {
+                    return (desired, connections);
+                }
+                match snapshot_connections.iter_mut().find(|(d, _)| *d == desired) {
+                    Some((_, connections)) => {
+                        debug!(
+                            "Using {} connections from last good snapshot for desired connection from {} to {}",
+                            connections.len(),
+                            desired.start,
+                            desired.destination
+                        );
+                        if let Some(station) = snapshot_start_stations.get(&desired.id()) {
+                            start_stations.insert(desired.id(), station.clone());
+                        }
+                        (desired, std::mem::take(connections))
+                    }
+                    None => (desired, connections),
+                }
+            })
+            .collect();
+        Self {
+            connections,
+            start_stations,
+            ..self
+        }
+    }
+
+    /// Whether `connection` of the `desired` route was shown by the last run.
+    pub fn was_shown(&self, desired: &DesiredConnection, connection: &Connection) -> bool {
+        self.shown.contains(&connection_key(desired, connection))
+    }
+
+    /// Remember the connections with the given `keys` as shown by this run.
+    pub fn with_shown(self, keys: Vec<String>) -> Self {
+        Self {
+            shown: keys,
+            ..self
+        }
+    }
+
+    /// Remember the connections with the given `keys` as shown, in addition to connections shown before.
+    ///
+    /// Forget connections shown before which are no longer cached, so that
+    /// the shown connections do not pile up.
+    pub fn add_shown(self, keys: Vec<String>) -> Self {
+        let cached = self
+            .all_connections()
+            .into_iter()
+            .map(|(desired, c)| connection_key(desired, c))
+            .collect::<HashSet<_>>();
+        let mut shown = self
+            .shown
+            .iter()
+            .filter(|key| cached.contains(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            if !shown.contains(&key) {
+                shown.push(key);
+            }
+        }
+        self.with_shown(shown)
+    }
+
+    /// Merge connections which only differ in walking to an adjacent stop.
+    ///
+    /// Among connections of a route which share the same first and final
+    /// ride and arrive at the same time keep only the one which walks least
+    /// before its first ride.
+    #[instrument(skip(self))]
+    pub fn evict_adjacent_stop_walks(self) -> Self {
+        let connections = self
+            .connections
+            .into_iter()
+            .map(|(desired, connections)| {
+                let len_before = connections.len();
+                let mut remaining_connections: Vec<Connection> = Vec::with_capacity(len_before);
+                for connection in connections {
+                    let key = ride_key(&connection);
+                    match remaining_connections
+                        .iter_mut()
+                        .find(|c| key.is_some() && ride_key(c) == key)
+                    {
+                        Some(existing)
+                            if initial_walking_time(&connection)
+                                < initial_walking_time(existing) =>
+                        {
+                            log_eviction(&desired, existing, EvictionReason::AdjacentStopWalk);
+                            *existing = connection
+                        }
+                        Some(_) => {
+                            log_eviction(&desired, &connection, EvictionReason::AdjacentStopWalk)
+                        }
+                        None => remaining_connections.push(connection),
+                    }
+                }
+                debug!(
+                    "Evicted {} connections walking to adjacent stops for desired connection from {} to {}",
+                    len_before - remaining_connections.len(),
+                    desired.start,
+                    desired.destination
+                );
+                (desired, remaining_connections)
+            })
+            .collect();
+        Self {
+            connections,
+            ..self
+        }
+    }
+
+    /// Remove all connections which are of no use, regardless of the time.
+    ///
+    /// Merge connections which walk to an adjacent stop first, and then
+    /// remove connections which start with a footway.  Merge first, so that
+    /// a connection which walks to an adjacent stop to catch the same rides
+    /// counts as merged, and not as starting with a footway.
+    ///
+    /// Keep cancelled connections, to show them as cancelled.
+    pub fn evict_unusable_connections(self) -> Self {
+        self.evict_adjacent_stop_walks()
+            .evict_starts_with_pedestrian()
     }
 
     /// Remove all connections which start with a footway.
@@ -99,13 +810,13 @@ impl ConnectionsCache {
                     connections
                 } else {
                     let len_before = connections.len();
-                    let remaining_connections = connections
-                        .into_iter()
-                        // Remove everything that starts with a walk
-                        .filter(|c| {
-                            c.departure().line_transport_type() != TransportType::Pedestrian
-                        })
-                        .collect::<Vec<_>>();
+                    // Remove everything that starts with a walk
+                    let remaining_connections = retain_connections(
+                        &desired,
+                        connections,
+                        EvictionReason::PedestrianStart,
+                        |c| c.departure().line_transport_type() != TransportType::Pedestrian,
+                    );
                     debug!(
                         "Evicted {} unreachable connections for desired connection from {} to {}",
                         len_before - remaining_connections.len(),
@@ -117,16 +828,59 @@ impl ConnectionsCache {
                 (desired, connections)
             })
             .collect();
-        Self { connections }
+        Self {
+            connections,
+            ..self
+        }
+    }
+
+    /// Remove all connections of routes requested for a start time after `start`.
+    ///
+    /// These routes lack connections between `start` and the time they were
+    /// requested for.  Compare start times to the minute, so that runs for
+    /// the same start time reuse cached connections.  Keep connections of
+    /// routes whose start time is not known.
+    #[instrument(skip(self), fields(start=%start))]
+    pub fn evict_requested_after(self, start: DateTime<Utc>) -> Self {
+        let start = truncate_to_minute(start);
+        let connections = self
+            .connections
+            .into_iter()
+            .map(|(desired, connections)| {
+                let requested_after = self
+                    .start_times
+                    .get(&desired.id())
+                    .is_some_and(|requested| start < *requested);
+                let connections = if requested_after {
+                    evict_connections(&desired, connections, EvictionReason::Refresh)
+                } else {
+                    connections
+                };
+                (desired, connections)
+            })
+            .collect();
+        Self {
+            connections,
+            ..self
+        }
     }
 
     /// Remove all connections which can't be reached anymore.
     ///
     /// Remove a connection if its actual start is before the given current
-    /// time, or if half of the required time to walk to the start is already
-    /// past.
+    /// time, or if too much of the required time to walk to the start is
+    /// already past, according to `reachability`.
+    ///
+    /// All comparisons are made between absolute points in time, never
+    /// between local wall clock times: `now` is in UTC and departure times
+    /// carry their own fixed UTC offset, so connections around a daylight
+    /// saving transition are evicted correctly.
     #[instrument(skip(self), fields(now=%now))]
-    pub fn evict_unreachable_connections(self, now: DateTime<Utc>) -> Self {
+    pub fn evict_unreachable_connections(
+        self,
+        now: DateTime<Utc>,
+        reachability: Reachability,
+    ) -> Self {
         let connections = self
             .connections
             .into_iter()
@@ -135,17 +889,22 @@ impl ConnectionsCache {
                     connections
                 } else {
                     let len_before = connections.len();
-                    let remaining_connections = connections
-                        .into_iter()
-                        // Connections must start strictly after the current time; we can get a train which already
-                        // left the station.
-                        .filter(|c| now <= c.planned_departure_time())
-                        // We still must have at least half of time time to walk to connection start, or we'll definitely
-                        // miss the train.
-                        .filter(|c| {
-                            now <= (c.planned_departure_time() - (desired.walk_to_start / 2))
-                        })
-                        .collect::<Vec<_>>();
+                    let remaining_connections = retain_connections(
+                        &desired,
+                        connections,
+                        EvictionReason::Unreachable,
+                        |c| {
+                            // Connections must start strictly after the current time; we can get a train which already
+                            // left the station.
+                            let departs_later = now <= c.planned_departure_time();
+                            // We still must have enough time to walk to connection start, or we'll definitely
+                            // miss the train.
+                            let time_to_walk = now
+                                <= (c.planned_departure_time()
+                                    - reachability.required_walk_time(desired.walk_to_start));
+                            departs_later && time_to_walk
+                        },
+                    );
                     debug!(
                         "Evicted {} unreachable connections for desired connection from {} to {}",
                         len_before - remaining_connections.len(),
@@ -157,7 +916,111 @@ impl ConnectionsCache {
                 (desired, connections)
             })
             .collect();
-        Self { connections }
+        Self {
+            connections,
+            ..self
+        }
+    }
+
+    /// Routes whose connections all depart after `now`, but none leaves time to walk to the start.
+    ///
+    /// [`Self::evict_unreachable_connections`] evicts all connections of
+    /// these routes only because of the time to walk to their start, which
+    /// suggests that this time is too large.
+    pub fn routes_too_far_to_walk(
+        &self,
+        now: DateTime<Utc>,
+        reachability: Reachability,
+    ) -> Vec<&DesiredConnection> {
+        self.routes()
+            .filter(|(desired, connections)| {
+                let required_walk_time = reachability.required_walk_time(desired.walk_to_start);
+                !connections.is_empty()
+                    && connections.iter().all(|c| {
+                        now <= c.planned_departure_time()
+                            && c.planned_departure_time() - required_walk_time < now
+                    })
+            })
+            .map(|(desired, _)| desired)
+            .collect()
+    }
+
+    /// Remove all connections of all routes.
+    ///
+    /// This forces a refresh of every route, but unlike discarding the
+    /// cache keeps everything else in the cache.
+    #[instrument(skip(self))]
+    pub fn evict_all_connections(self) -> Self {
+        let connections = self
+            .connections
+            .into_iter()
+            .map(|(desired, connections)| {
+                let connections = evict_connections(&desired, connections, EvictionReason::Refresh);
+                (desired, connections)
+            })
+            .collect();
+        Self {
+            connections,
+            ..self
+        }
+    }
+
+    /// Remove all connections of routes matching `pattern`.
+    ///
+    /// This forces a refresh of only these routes; see
+    /// [`DesiredConnection::matches_route`] for how routes match.
+    #[instrument(skip(self))]
+    pub fn evict_routes_matching(self, pattern: &str) -> Self {
+        let connections = self
+            .connections
+            .into_iter()
+            .map(|(desired, connections)| {
+                let connections = if desired.matches_route(pattern) {
+                    debug!(
+                        "Evicting connections for desired connection from {} to {} matching {}",
+                        desired.start, desired.destination, pattern
+                    );
+                    evict_connections(&desired, connections, EvictionReason::Refresh)
+                } else {
+                    connections
+                };
+                (desired, connections)
+            })
+            .collect();
+        Self {
+            connections,
+            ..self
+        }
+    }
+
+    /// Remove all connections of routes with a connection departing at `time`.
+    ///
+    /// This forces a refresh of these routes, to get up to date real time
+    /// information for the connection departing at `time`.
+    #[instrument(skip(self), fields(time=%time))]
+    pub fn evict_routes_departing_at(self, time: NaiveTime) -> Self {
+        let connections = self
+            .connections
+            .into_iter()
+            .map(|(desired, connections)| {
+                let connections = if connections.iter().any(|c| departs_at(c, time)) {
+                    debug!(
+                        "Evicting connections for desired connection from {} to {} to refresh connection at {}",
+                        desired.start,
+                        desired.destination,
+                        time
+                    );
+                    evict_connections(&desired, connections, EvictionReason::Refresh)
+                } else {
+                    connections
+                };
+                (desired, connections)
+            })
+            .collect();
+        Self {
+            connections,
+            ..self
+        }
     }
 
     /// Remove connections if there are too few connections.
@@ -179,24 +1042,104 @@ impl ConnectionsCache {
                         desired.start,
                         desired.destination,
                     );
-                    Vec::new()
+                    evict_connections(&desired, connections, EvictionReason::TooFew)
                 };
                 (desired, connections)
             })
             .collect();
-        Self { connections }
+        Self {
+            connections,
+            ..self
+        }
     }
 
-    /// Refresh desired connections with the given `update` function.
+    /// Explain which eviction stage would evict each cached connection at `start`.
     ///
-    /// Call `update` for every desired connection with an empty list of connections.
+    /// Run every eviction stage in the order of a regular run, without
+    /// refreshing any route, and return every cached connection with the
+    /// reason of the first stage which evicts it, or `None` if it survives
+    /// all stages.
+    pub fn explain_eviction(
+        &self,
+        start: DateTime<Utc>,
+        reachability: Reachability,
+    ) -> Vec<(&DesiredConnection, &Connection, Option<EvictionReason>)> {
+        type Stage = Box<dyn Fn(ConnectionsCache) -> ConnectionsCache>;
+        let stages: Vec<(EvictionReason, Stage)> = vec![
+            (
+                EvictionReason::Refresh,
+                Box::new(move |cache| cache.evict_requested_after(start)),
+            ),
+            (
+                EvictionReason::Unreachable,
+                Box::new(move |cache| cache.evict_unreachable_connections(start, reachability)),
+            ),
+            (
+                EvictionReason::TooFew,
+                Box::new(|cache| cache.evict_too_few_connections(MIN_CACHED_CONNECTIONS)),
+            ),
+            // The stages of evict_unusable_connections, in the same order
+            (
+                EvictionReason::AdjacentStopWalk,
+                Box::new(|cache| cache.evict_adjacent_stop_walks()),
+            ),
+            (
+                EvictionReason::PedestrianStart,
+                Box::new(|cache| cache.evict_starts_with_pedestrian()),
+            ),
+        ];
+        let keys = |cache: &ConnectionsCache| {
+            cache
+                .routes()
+                .flat_map(|(desired, connections)| {
+                    connections
+                        .iter()
+                        .map(move |c| (desired.id(), c.signature()))
+                })
+                .collect::<HashSet<_>>()
+        };
+        let mut fates = HashMap::new();
+        let mut current = self.clone();
+        for (reason, stage) in stages {
+            let before = keys(&current);
+            current = stage(current);
+            let after = keys(&current);
+            for key in before.difference(&after) {
+                fates.insert(key.clone(), reason);
+            }
+        }
+        self.all_connections()
+            .into_iter()
+            .map(|(desired, connection)| {
+                let fate = fates.get(&(desired.id(), connection.signature())).copied();
+                (desired, connection, fate)
+            })
+            .collect()
+    }
+
+    /// Refresh desired connections for the given `start` time with the given `update` function.
+    ///
+    /// Call `update` for every desired connection with an empty list of
+    /// connections, and remember `start` for every refreshed route.
+    /// `update` returns the desired connection along with the start station
+    /// it resolved and the new connections.
+    ///
+    /// If `update` fails for some routes, log the error and leave these
+    /// routes without connections.  Fail only if `update` fails for every
+    /// route it was called for, and no other route has cached connections,
+    /// with the first error.
     #[instrument(skip_all)]
-    pub async fn refresh_empty<E, F, U>(self, update: U) -> std::result::Result<Self, E>
+    pub async fn refresh_empty<E, F, U>(
+        self,
+        start: DateTime<Utc>,
+        update: U,
+    ) -> std::result::Result<Self, E>
     where
+        E: std::fmt::Display,
         U: Fn(DesiredConnection) -> F,
-        F: Future<Output = std::result::Result<(DesiredConnection, Vec<Connection>), E>>,
+        F: Future<Output = std::result::Result<(DesiredConnection, Station, Vec<Connection>), E>>,
     {
-        let connections = join_all(self
+        let refreshed = join_all(self
             .connections
             .into_iter()
             .map(|(desired, connections)| {
@@ -204,39 +1147,1429 @@ impl ConnectionsCache {
                 async {
                     if connections.is_empty() {
                         event!(Level::INFO, "Desired connection from {} to {} has no cached connections, refreshing connections", desired.start, desired.destination);
-                        update(desired).await
+                        update(desired.clone()).await.map(|(desired, station, connections)| {
+                            (desired, Some(station), connections)
+                        }).map_err(|error| (desired, error))
                     } else {
-                        Ok((desired, connections))
+                        Ok((desired, None, connections))
                     }
                 }.instrument(update_span)
             })
             .collect::<Vec<_>>())
-            .await
+            .await;
+
+        let mut first_error = None;
+        let mut any_usable = false;
+        let mut start_stations = self.start_stations;
+        let mut start_times = self.start_times;
+        let connections = refreshed
             .into_iter()
-            .collect::<Result<Vec<_>, E>>()?;
+            .map(|result| match result {
+                Ok((desired, station, connections)) => {
+                    any_usable |= station.is_some() || !connections.is_empty();
+                    if let Some(station) = station {
+                        start_stations.insert(desired.id(), station.name().to_string());
+                        start_times.insert(desired.id(), truncate_to_minute(start));
+                    }
+                    (desired, connections)
+                }
+                Err((desired, error)) => {
+                    event!(
+                        Level::WARN,
+                        "Failed to refresh connections from {} to {}: {:#}",
+                        desired.start,
+                        desired.destination,
+                        error
+                    );
+                    first_error.get_or_insert(error);
+                    (desired, Vec::new())
+                }
+            })
+            .collect();
+        match first_error {
+            Some(error) if !any_usable => Err(error),
+            _ => Ok(Self {
+                connections,
+                start_stations,
+                start_times,
+                ..self
+            }),
+        }
+    }
 
-        Ok(Self { connections })
+    /// Whether `connection` of the `desired` route departs at a different
+    /// stop than the start station resolved for the route.
+    ///
+    /// `false` if the start station of the route is not known.
+    pub fn has_unexpected_start(
+        &self,
+        desired: &DesiredConnection,
+        connection: &Connection,
+    ) -> bool {
+        self.start_stations
+            .get(&desired.id())
+            .is_some_and(|name| name != connection.departure().from().name())
     }
 
-    /// Return all connections for all desired routes, ordered ascending by start time, with the walk distance to start.
-    pub fn all_connections(&self) -> Vec<(Duration, &Connection)> {
-        let mut connections = self
-            .connections
+    /// Return the connections of every desired route, along with the desired route.
+    ///
+    /// Skip connections starting with an ignored line.
+    fn connections_per_route(
+        &self,
+    ) -> impl Iterator<Item = impl Iterator<Item = (&DesiredConnection, &Connection)>> {
+        self.routes().map(|(desired, connections)| {
+            connections
+                .iter()
+                .filter(|c| {
+                    desired.ignore_starting_with.is_empty()
+                        || (!desired
+                            .ignore_starting_with
+                            .iter()
+                            .any(|l| c.departure().line_label() == l))
+                })
+                .filter(|c| {
+                    !c.transfer_stops()
+                        .any(|stop| desired.avoid_stops.iter().any(|s| s == stop))
+                })
+                .map(move |connection| (desired, connection))
+        })
+    }
+
+    /// Iterate over every desired route with all its cached connections, in the order of the configuration.
+    ///
+    /// Unlike [`Self::all_connections`] this includes connections starting
+    /// with ignored lines or changing at avoided stops.
+    pub fn routes(&self) -> impl Iterator<Item = (&DesiredConnection, &[Connection])> {
+        self.connections
             .iter()
-            .flat_map(|(desired, connections)| {
+            .map(|(desired, connections)| (desired, connections.as_slice()))
+    }
+
+    /// Whether routes matching `predicate` have connections, but no real time information for any of them.
+    ///
+    /// Use `predicate` to only check routes just refreshed, since connections
+    /// far ahead never have real time information.  If routes just refreshed
+    /// lack real time information the real time feed of the MVG API is down.
+    pub fn lacks_realtime_information<P>(&self, predicate: P) -> bool
+    where
+        P: Fn(&DesiredConnection) -> bool,
+    {
+        let mut connections = self
+            .routes()
+            .filter(|(desired, _)| predicate(desired))
+            .flat_map(|(_, c)| c)
+            .peekable();
+        connections.peek().is_some() && !connections.any(|c| c.has_realtime_information())
+    }
+
+    /// Return all connections for all desired routes, ordered ascending by start time, with their desired route.
+    pub fn all_connections(&self) -> Vec<(&DesiredConnection, &Connection)> {
+        self.connections_in_order().collect()
+    }
+
+    /// Iterate over all connections for all desired routes, ordered ascending by start time, with their desired route.
+    ///
+    /// Unlike [`Self::all_connections`] this merges the connections of all
+    /// routes lazily, so taking only the first few connections does not
+    /// order all connections.
+    pub fn connections_in_order(&self) -> impl Iterator<Item = (&DesiredConnection, &Connection)> {
+        let mut routes = self
+            .connections_per_route()
+            .map(|connections| {
+                let mut connections = connections.collect::<Vec<_>>();
+                connections.sort_by_key(|(desired, c)| order_key(desired, c));
+                connections.into_iter().peekable()
+            })
+            .collect::<Vec<_>>();
+        std::iter::from_fn(move || {
+            // Take from the first route with the earliest next connection, to
+            // order connections which start at the same time by route.
+            let (index, _) = routes
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, route)| {
+                    route
+                        .peek()
+                        .map(|(desired, c)| (index, order_key(desired, c)))
+                })
+                .min_by_key(|(_, start)| *start)?;
+            routes[index].next()
+        })
+    }
+
+    /// Return the earliest connection matching `predicate` of every desired route, ordered ascending by start time, with their desired route.
+    pub fn earliest_connections<P>(&self, predicate: P) -> Vec<(&DesiredConnection, &Connection)>
+    where
+        P: Fn(&Connection) -> bool,
+    {
+        self.connections_limited_per_route(1, predicate)
+    }
+
+    /// Return at most `limit` connections matching `predicate` of every desired route, ordered ascending by start time, with their desired route.
+    ///
+    /// Take the earliest connections of every route which match
+    /// `predicate`, so that connections which do not match do not take the
+    /// place of connections which do.
+    pub fn connections_limited_per_route<P>(
+        &self,
+        limit: usize,
+        predicate: P,
+    ) -> Vec<(&DesiredConnection, &Connection)>
+    where
+        P: Fn(&Connection) -> bool,
+    {
+        let mut connections = self
+            .connections_per_route()
+            .flat_map(|connections| {
+                let mut connections = connections
+                    .filter(|(_, c)| predicate(c))
+                    .collect::<Vec<_>>();
+                connections.sort_by_key(|(desired, c)| order_key(desired, c));
+                connections.truncate(limit);
                 connections
-                    .iter()
-                    .filter(|c| {
-                        desired.ignore_starting_with.is_empty()
-                            || (!desired
-                                .ignore_starting_with
-                                .iter()
-                                .any(|l| c.departure().line_label() == l))
-                    })
-                    .map(|connection| (desired.walk_to_start, connection))
             })
             .collect::<Vec<_>>();
-        connections.sort_by_key(|(walk_to_start, c)| c.planned_departure_time() - *walk_to_start);
+        connections.sort_by_key(|(desired, c)| order_key(desired, c));
+        connections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::*;
+    use crate::mvg::ConnectionPart;
+    use chrono::{DateTime, Duration, FixedOffset, NaiveDate, TimeZone};
+    use pretty_assertions::assert_eq;
+
+    fn time(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    fn desired(walk_to_start: Duration) -> DesiredConnection {
+        DesiredConnection {
+            start: "Start".to_string(),
+            destination: "Destination".to_string(),
+            walk_to_start,
+            ignore_starting_with: Vec::new(),
+            preferred_times: Vec::new(),
+            active_days: Vec::new(),
+            active_hours: None,
+            onward: None,
+            avoid_stops: Vec::new(),
+            color: None,
+        }
+    }
+
+    fn cache_with_departures(walk_to_start: Duration, departures: &[&str]) -> ConnectionsCache {
+        let connections = departures
+            .iter()
+            .map(|departure| {
+                let departure = time(departure);
+                Connection::single_part(
+                    TransportType::UBahn,
+                    "U6",
+                    departure,
+                    departure + Duration::minutes(20),
+                )
+            })
+            .collect();
+        ConnectionsCache {
+            connections: vec![(desired(walk_to_start), connections)],
+            ..ConnectionsCache::default()
+        }
+    }
+
+    fn departures_of(
+        connections: &[(&DesiredConnection, &Connection)],
+    ) -> Vec<DateTime<FixedOffset>> {
         connections
+            .iter()
+            .map(|(_, c)| c.planned_departure_time())
+            .collect()
+    }
+
+    fn departures(cache: &ConnectionsCache) -> Vec<DateTime<FixedOffset>> {
+        cache
+            .all_connections()
+            .iter()
+            .map(|(_, c)| c.planned_departure_time())
+            .collect()
+    }
+
+    #[test]
+    fn evict_unreachable_connections_across_spring_dst_transition() {
+        // At 02:00 CET clocks jump forward to 03:00 CEST, i.e. 01:00 UTC.
+        let cache = cache_with_departures(
+            Duration::minutes(10),
+            &["2024-03-31T03:04:00+02:00", "2024-03-31T03:06:00+02:00"],
+        );
+        // 03:00 CEST; half of the walk time is already past for 03:04 CEST,
+        // even though the wall clock time 02:00 CET would be well before it.
+        let now = time("2024-03-31T01:00:00+00:00").with_timezone(&Utc);
+        let cache = cache.evict_unreachable_connections(now, Reachability::Lenient);
+        assert_eq!(departures(&cache), vec![time("2024-03-31T03:06:00+02:00")]);
+    }
+
+    #[test]
+    fn evict_unreachable_connections_across_autumn_dst_transition() {
+        // At 03:00 CEST clocks fall back to 02:00 CET, i.e. 01:00 UTC.
+        let cache = cache_with_departures(
+            Duration::minutes(10),
+            &["2024-10-27T02:05:00+01:00", "2024-10-27T02:57:00+02:00"],
+        );
+        // 02:55 CEST; the 02:05 CET connection leaves in ten minutes even
+        // though its wall clock time is before the current wall clock time,
+        // whereas the 02:57 CEST connection is unreachable.
+        let now = time("2024-10-27T02:55:00+02:00").with_timezone(&Utc);
+        let cache = cache.evict_unreachable_connections(now, Reachability::Lenient);
+        assert_eq!(departures(&cache), vec![time("2024-10-27T02:05:00+01:00")]);
+    }
+
+    #[test]
+    fn evict_unreachable_connections_strictly() {
+        let times = ["2024-06-01T17:32:00+02:00", "2024-06-01T17:42:00+02:00"];
+        let now = time("2024-06-01T17:25:00+02:00").with_timezone(&Utc);
+        let lenient = cache_with_departures(Duration::minutes(10), &times)
+            .evict_unreachable_connections(now, Reachability::Lenient);
+        assert_eq!(
+            departures(&lenient),
+            vec![
+                time("2024-06-01T17:32:00+02:00"),
+                time("2024-06-01T17:42:00+02:00")
+            ]
+        );
+        let strict = cache_with_departures(Duration::minutes(10), &times)
+            .evict_unreachable_connections(now, Reachability::Strict);
+        assert_eq!(departures(&strict), vec![time("2024-06-01T17:42:00+02:00")]);
+    }
+
+    #[test]
+    fn evict_starts_with_pedestrian_from_fixture() {
+        let mut connections = Connection::from_fixture("pedestrian_start");
+        connections.extend(Connection::from_fixture("connections_with_delays"));
+        let cache = ConnectionsCache {
+            connections: vec![(desired(Duration::minutes(5)), connections)],
+            ..ConnectionsCache::default()
+        }
+        .evict_starts_with_pedestrian();
+        assert_eq!(
+            departures(&cache),
+            vec![
+                time("2024-06-01T17:42:00+02:00"),
+                time("2024-06-01T17:47:00+02:00")
+            ]
+        );
+    }
+
+    #[test]
+    fn evict_adjacent_stop_walks_from_fixture() {
+        let mut connections = Connection::from_fixture("pedestrian_start");
+        connections.extend(Connection::from_fixture("connections_with_delays"));
+        let cache = ConnectionsCache {
+            connections: vec![(desired(Duration::minutes(5)), connections)],
+            ..ConnectionsCache::default()
+        }
+        .evict_adjacent_stop_walks();
+        // The S3 from Hauptbahnhof at 17:47 replaces the walk from
+        // Hauptbahnhof Nord to the same S3, even though the walk came first.
+        let connections = &cache.connections[0].1;
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].departure().from().name(), "Hauptbahnhof");
+        assert_eq!(connections[0].departure().line_label(), "S3");
+        assert_eq!(connections[1].departure().line_label(), "U4");
+    }
+
+    #[test]
+    fn keep_different_first_rides_to_the_same_final_ride() {
+        let departure = time("2024-06-01T17:42:00+02:00");
+        let via = |line: &str| {
+            Connection::from_parts(vec![
+                ConnectionPart::new(
+                    TransportType::Bus,
+                    line,
+                    departure,
+                    departure + Duration::minutes(5),
+                )
+                .between("Start", "Hauptbahnhof"),
+                ConnectionPart::new(
+                    TransportType::SBahn,
+                    "S3",
+                    departure + Duration::minutes(10),
+                    departure + Duration::minutes(20),
+                )
+                .between("Hauptbahnhof", "Destination"),
+            ])
+        };
+        let cache = ConnectionsCache {
+            connections: vec![(desired(Duration::minutes(5)), vec![via("58"), via("150")])],
+            ..ConnectionsCache::default()
+        }
+        .evict_adjacent_stop_walks();
+        assert_eq!(cache.connections[0].1.len(), 2);
+    }
+
+    #[test]
+    fn merge_adjacent_stop_walks_before_evicting_pedestrian_starts() {
+        let mut connections = Connection::from_fixture("pedestrian_start");
+        connections.extend(Connection::from_fixture("connections_with_delays"));
+        let cache = ConnectionsCache {
+            connections: vec![(desired(Duration::minutes(5)), connections)],
+            ..ConnectionsCache::default()
+        };
+        let now = time("2024-06-01T17:00:00+02:00").with_timezone(&Utc);
+        let walk = cache
+            .explain_eviction(now, Reachability::Lenient)
+            .into_iter()
+            .find(|(_, c, _)| c.departure().line_transport_type() == TransportType::Pedestrian)
+            .unwrap();
+        assert_eq!(walk.2, Some(EvictionReason::AdjacentStopWalk));
+        let remaining = cache.evict_unusable_connections();
+        assert!(remaining
+            .all_connections()
+            .iter()
+            .all(|(_, c)| c.departure().line_transport_type() != TransportType::Pedestrian));
+    }
+
+    #[test]
+    fn evict_unreachable_connections_from_fixture_with_delays() {
+        let cache = ConnectionsCache {
+            connections: vec![(
+                desired(Duration::minutes(10)),
+                Connection::from_fixture("connections_with_delays"),
+            )],
+            ..ConnectionsCache::default()
+        };
+        // Reachability goes by planned departure; the U4 at 17:42 is out of
+        // reach with half of the walk time, but the S3 at 17:47 is not.
+        let now = time("2024-06-01T17:40:00+02:00").with_timezone(&Utc);
+        let cache = cache.evict_unreachable_connections(now, Reachability::Lenient);
+        assert_eq!(departures(&cache), vec![time("2024-06-01T17:47:00+02:00")]);
+    }
+
+    #[test]
+    fn earliest_connections_takes_one_connection_per_route() {
+        let mut cache = cache_with_departures(
+            Duration::minutes(5),
+            &[
+                "2024-06-01T17:42:00+02:00",
+                "2024-06-01T17:32:00+02:00",
+                "2024-06-01T17:52:00+02:00",
+            ],
+        );
+        cache.connections.extend(
+            cache_with_departures(
+                Duration::minutes(10),
+                &["2024-06-01T17:40:00+02:00", "2024-06-01T17:50:00+02:00"],
+            )
+            .connections,
+        );
+        let earliest = cache
+            .earliest_connections(|_| true)
+            .iter()
+            .map(|(_, c)| c.planned_departure_time())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            earliest,
+            vec![
+                time("2024-06-01T17:32:00+02:00"),
+                time("2024-06-01T17:40:00+02:00")
+            ]
+        );
+    }
+
+    /// The serialized form of [`populated_cache`] in version 0 of the cache format.
+    ///
+    /// If this test fails after a change to the cache format, every user's
+    /// cache breaks upon update.
+    const GOLDEN_CACHE_V0: &[u8] = &[
+        99, 111, 110, 110, 101, 99, 116, 105, 111, 110, 115, 0, 115, 116, 97, 114, 116, 0, 5, 83,
+        116, 97, 114, 116, 0, 100, 101, 115, 116, 105, 110, 97, 116, 105, 111, 110, 0, 11, 68, 101,
+        115, 116, 105, 110, 97, 116, 105, 111, 110, 0, 119, 97, 108, 107, 95, 116, 111, 95, 115,
+        116, 97, 114, 116, 0, 105, 103, 110, 111, 114, 101, 95, 115, 116, 97, 114, 116, 105, 110,
+        103, 95, 119, 105, 116, 104, 0, 2, 85, 53, 0, 1, 4, 20, 4, 68, 30, 83, 46, 0, 5, 0, 1, 0,
+        4, 0, 66, 0, 16, 0, 89, 0, 44, 1, 20, 40, 20, 5, 112, 97, 114, 116, 115, 0, 102, 114, 111,
+        109, 0, 110, 97, 109, 101, 0, 5, 83, 116, 97, 114, 116, 0, 112, 108, 97, 110, 110, 101,
+        100, 68, 101, 112, 97, 114, 116, 117, 114, 101, 0, 25, 50, 48, 50, 52, 45, 48, 54, 45, 48,
+        49, 84, 49, 55, 58, 51, 50, 58, 48, 48, 43, 48, 50, 58, 48, 48, 0, 100, 101, 112, 97, 114,
+        116, 117, 114, 101, 68, 101, 108, 97, 121, 73, 110, 77, 105, 110, 117, 116, 101, 115, 0, 3,
+        25, 82, 71, 3, 1, 3, 0, 82, 59, 0, 20, 20, 116, 111, 0, 11, 68, 101, 115, 116, 105, 110,
+        97, 116, 105, 111, 110, 0, 25, 50, 48, 50, 52, 45, 48, 54, 45, 48, 49, 84, 49, 55, 58, 53,
+        50, 58, 48, 48, 43, 48, 50, 58, 48, 48, 0, 97, 114, 114, 105, 118, 97, 108, 68, 101, 108,
+        97, 121, 73, 110, 77, 105, 110, 117, 116, 101, 115, 0, 3, 23, 160, 149, 3, 1, 3, 0, 69, 57,
+        0, 20, 20, 108, 105, 110, 101, 0, 108, 97, 98, 101, 108, 0, 2, 85, 54, 0, 116, 114, 97,
+        110, 115, 112, 111, 114, 116, 84, 121, 112, 101, 0, 5, 85, 66, 65, 72, 78, 0, 2, 32, 23, 2,
+        1, 2, 30, 13, 20, 20, 3, 223, 48, 127, 3, 1, 3, 137, 12, 61, 36, 36, 36, 1, 7, 36, 1, 245,
+        1, 1, 1, 7, 40, 5, 83, 116, 97, 114, 116, 0, 25, 50, 48, 50, 52, 45, 48, 54, 45, 48, 49,
+        84, 49, 55, 58, 52, 50, 58, 48, 48, 43, 48, 50, 58, 48, 48, 0, 0, 3, 0, 221, 0, 23, 1, 13,
+        1, 6, 2, 3, 0, 46, 40, 0, 20, 20, 11, 68, 101, 115, 116, 105, 110, 97, 116, 105, 111, 110,
+        0, 25, 50, 48, 50, 52, 45, 48, 54, 45, 48, 49, 84, 49, 56, 58, 48, 50, 58, 48, 48, 43, 48,
+        50, 58, 48, 48, 0, 0, 3, 0, 199, 0, 81, 1, 71, 1, 6, 2, 3, 0, 52, 40, 0, 20, 20, 2, 85, 54,
+        0, 5, 85, 66, 65, 72, 78, 0, 2, 186, 177, 2, 1, 2, 16, 13, 20, 20, 3, 0, 122, 1, 204, 0,
+        28, 1, 6, 2, 3, 96, 16, 40, 36, 36, 36, 1, 7, 36, 1, 0, 148, 1, 2, 2, 1, 9, 40, 2, 163, 4,
+        36, 36, 2, 0, 174, 1, 8, 0, 37, 40, 1, 7, 41, 0, 1, 0, 34, 2, 2, 2, 1, 10, 40, 2, 36, 1,
+    ];
+
+    /// The serialized form of [`populated_cache`] in version 1 of the cache format.
+    const GOLDEN_CACHE_V1: &[u8] = &[
+        118, 101, 114, 115, 105, 111, 110, 0, 99, 111, 110, 110, 101, 99, 116, 105, 111, 110, 115,
+        0, 115, 116, 97, 114, 116, 0, 5, 83, 116, 97, 114, 116, 0, 100, 101, 115, 116, 105, 110,
+        97, 116, 105, 111, 110, 0, 11, 68, 101, 115, 116, 105, 110, 97, 116, 105, 111, 110, 0, 119,
+        97, 108, 107, 95, 116, 111, 95, 115, 116, 97, 114, 116, 0, 105, 103, 110, 111, 114, 101,
+        95, 115, 116, 97, 114, 116, 105, 110, 103, 95, 119, 105, 116, 104, 0, 2, 85, 53, 0, 1, 4,
+        20, 4, 68, 30, 83, 46, 0, 5, 0, 1, 0, 4, 0, 66, 0, 16, 0, 89, 0, 44, 1, 20, 40, 20, 5, 112,
+        97, 114, 116, 115, 0, 102, 114, 111, 109, 0, 110, 97, 109, 101, 0, 5, 83, 116, 97, 114,
+        116, 0, 112, 108, 97, 110, 110, 101, 100, 68, 101, 112, 97, 114, 116, 117, 114, 101, 0, 25,
+        50, 48, 50, 52, 45, 48, 54, 45, 48, 49, 84, 49, 55, 58, 51, 50, 58, 48, 48, 43, 48, 50, 58,
+        48, 48, 0, 100, 101, 112, 97, 114, 116, 117, 114, 101, 68, 101, 108, 97, 121, 73, 110, 77,
+        105, 110, 117, 116, 101, 115, 0, 3, 25, 82, 71, 3, 1, 3, 0, 82, 59, 0, 20, 20, 116, 111, 0,
+        11, 68, 101, 115, 116, 105, 110, 97, 116, 105, 111, 110, 0, 25, 50, 48, 50, 52, 45, 48, 54,
+        45, 48, 49, 84, 49, 55, 58, 53, 50, 58, 48, 48, 43, 48, 50, 58, 48, 48, 0, 97, 114, 114,
+        105, 118, 97, 108, 68, 101, 108, 97, 121, 73, 110, 77, 105, 110, 117, 116, 101, 115, 0, 3,
+        23, 160, 149, 3, 1, 3, 0, 69, 57, 0, 20, 20, 108, 105, 110, 101, 0, 108, 97, 98, 101, 108,
+        0, 2, 85, 54, 0, 116, 114, 97, 110, 115, 112, 111, 114, 116, 84, 121, 112, 101, 0, 5, 85,
+        66, 65, 72, 78, 0, 2, 32, 23, 2, 1, 2, 30, 13, 20, 20, 3, 223, 48, 127, 3, 1, 3, 137, 12,
+        61, 36, 36, 36, 1, 7, 36, 1, 245, 1, 1, 1, 7, 40, 5, 83, 116, 97, 114, 116, 0, 25, 50, 48,
+        50, 52, 45, 48, 54, 45, 48, 49, 84, 49, 55, 58, 52, 50, 58, 48, 48, 43, 48, 50, 58, 48, 48,
+        0, 0, 3, 0, 221, 0, 23, 1, 13, 1, 6, 2, 3, 0, 46, 40, 0, 20, 20, 11, 68, 101, 115, 116,
+        105, 110, 97, 116, 105, 111, 110, 0, 25, 50, 48, 50, 52, 45, 48, 54, 45, 48, 49, 84, 49,
+        56, 58, 48, 50, 58, 48, 48, 43, 48, 50, 58, 48, 48, 0, 0, 3, 0, 199, 0, 81, 1, 71, 1, 6, 2,
+        3, 0, 52, 40, 0, 20, 20, 2, 85, 54, 0, 5, 85, 66, 65, 72, 78, 0, 2, 186, 177, 2, 1, 2, 16,
+        13, 20, 20, 3, 0, 122, 1, 204, 0, 28, 1, 6, 2, 3, 96, 16, 40, 36, 36, 36, 1, 7, 36, 1, 0,
+        148, 1, 2, 2, 1, 9, 40, 2, 163, 4, 36, 36, 2, 0, 174, 1, 8, 0, 37, 40, 1, 7, 41, 0, 2, 0,
+        34, 2, 44, 2, 4, 2, 2, 12, 1, 40, 8, 4, 36, 1,
+    ];
+
+    fn populated_cache() -> ConnectionsCache {
+        let mut cache = cache_with_departures(
+            Duration::minutes(5),
+            &["2024-06-01T17:32:00+02:00", "2024-06-01T17:42:00+02:00"],
+        );
+        cache.connections[0].0.ignore_starting_with = vec!["U5".to_string()];
+        cache
+    }
+
+    #[test]
+    fn roundtrip_cache() {
+        let cache = populated_cache();
+        let contents = flexbuffers::to_vec(&cache).unwrap();
+        let deserialized = ConnectionsCache::from_slice(&contents).unwrap();
+        assert_eq!(deserialized, cache);
+    }
+
+    #[test]
+    fn roundtrip_cache_through_json_file() {
+        let mut cache = populated_cache();
+        cache.connections[0]
+            .1
+            .extend(Connection::from_fixture("connections_with_delays"));
+        let path = std::env::temp_dir().join(format!("home-cache-{}.json", std::process::id()));
+        cache.save(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let loaded = ConnectionsCache::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            contents.contains("\"walk_to_start\": \"5m\""),
+            "Unexpected contents: {contents}"
+        );
+        assert_eq!(loaded, cache);
+    }
+
+    #[test]
+    fn reject_json_cache_of_other_version() {
+        let error =
+            ConnectionsCache::from_json(br#"{"version": 0, "connections": []}"#).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown cache version 0");
+    }
+
+    #[test]
+    fn deserialize_golden_cache_v0() {
+        let deserialized = ConnectionsCache::from_slice(GOLDEN_CACHE_V0).unwrap();
+        assert_eq!(deserialized, populated_cache());
+    }
+
+    #[test]
+    fn deserialize_golden_cache_v1() {
+        let deserialized = ConnectionsCache::from_slice(GOLDEN_CACHE_V1).unwrap();
+        assert_eq!(deserialized, populated_cache());
+    }
+
+    #[test]
+    fn deserialize_unknown_cache_version() {
+        let cache = ConnectionsCache {
+            version: CACHE_VERSION + 1,
+            ..populated_cache()
+        };
+        let contents = flexbuffers::to_vec(cache).unwrap();
+        let error = ConnectionsCache::from_slice(&contents).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Unknown cache version {}", CACHE_VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn limit_connections_per_route_after_filtering() {
+        let mut cache = cache_with_departures(
+            Duration::minutes(5),
+            &["2024-06-01T17:32:00+02:00", "2024-06-01T17:42:00+02:00"],
+        );
+        cache.connections[0].1[0] = Connection::single_part(
+            TransportType::Bus,
+            "58",
+            time("2024-06-01T17:32:00+02:00"),
+            time("2024-06-01T17:52:00+02:00"),
+        );
+        let earliest = cache
+            .earliest_connections(|c| c.departure().line_transport_type() != TransportType::Bus)
+            .iter()
+            .map(|(_, c)| c.planned_departure_time())
+            .collect::<Vec<_>>();
+        assert_eq!(earliest, vec![time("2024-06-01T17:42:00+02:00")]);
+    }
+
+    #[test]
+    fn connections_limited_per_route() {
+        let mut cache = cache_with_departures(
+            Duration::minutes(5),
+            &[
+                "2024-06-01T17:32:00+02:00",
+                "2024-06-01T17:34:00+02:00",
+                "2024-06-01T17:36:00+02:00",
+            ],
+        );
+        cache.connections.extend(
+            cache_with_departures(
+                Duration::minutes(5),
+                &["2024-06-01T17:50:00+02:00", "2024-06-01T17:40:00+02:00"],
+            )
+            .connections,
+        );
+        let limited = cache
+            .connections_limited_per_route(2, |_| true)
+            .iter()
+            .map(|(_, c)| c.planned_departure_time())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            limited,
+            vec![
+                time("2024-06-01T17:32:00+02:00"),
+                time("2024-06-01T17:34:00+02:00"),
+                time("2024-06-01T17:40:00+02:00"),
+                time("2024-06-01T17:50:00+02:00"),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefer_direct_connections_within_window() {
+        let time_with_transfers = time("2024-06-01T17:30:00+02:00");
+        let with_transfers = Connection::from_parts(vec![
+            ConnectionPart::new(
+                TransportType::UBahn,
+                "U6",
+                time_with_transfers,
+                time_with_transfers + Duration::minutes(10),
+            ),
+            ConnectionPart::new(
+                TransportType::Bus,
+                "53",
+                time_with_transfers + Duration::minutes(12),
+                time_with_transfers + Duration::minutes(20),
+            ),
+        ]);
+        let time_direct = time("2024-06-01T17:32:00+02:00");
+        let direct = Connection::single_part(
+            TransportType::Tram,
+            "19",
+            time_direct,
+            time_direct + Duration::minutes(25),
+        );
+        let time_later = time("2024-06-01T17:40:00+02:00");
+        let later = Connection::single_part(
+            TransportType::Tram,
+            "19",
+            time_later,
+            time_later + Duration::minutes(25),
+        );
+        let route = desired(Duration::zero());
+        let mut connections = vec![
+            (&route, &with_transfers),
+            (&route, &later),
+            (&route, &direct),
+        ];
+        prefer_direct_connections(&mut connections, Duration::minutes(5));
+        assert_eq!(
+            connections,
+            vec![
+                (&route, &direct),
+                (&route, &with_transfers),
+                (&route, &later)
+            ]
+        );
+    }
+
+    #[test]
+    fn prefer_direct_connections_within_window_of_first_connection() {
+        // 17:33 and 17:36 fall into different five minute intervals of the
+        // clock, but start within five minutes of each other.
+        let time_with_transfers = time("2024-06-01T17:33:00+02:00");
+        let with_transfers = Connection::from_parts(vec![
+            ConnectionPart::new(
+                TransportType::UBahn,
+                "U6",
+                time_with_transfers,
+                time_with_transfers + Duration::minutes(10),
+            ),
+            ConnectionPart::new(
+                TransportType::Bus,
+                "53",
+                time_with_transfers + Duration::minutes(12),
+                time_with_transfers + Duration::minutes(20),
+            ),
+        ]);
+        let time_direct = time("2024-06-01T17:36:00+02:00");
+        let direct = Connection::single_part(
+            TransportType::Tram,
+            "19",
+            time_direct,
+            time_direct + Duration::minutes(25),
+        );
+        let time_later = time("2024-06-01T17:39:00+02:00");
+        let later = Connection::single_part(
+            TransportType::Tram,
+            "19",
+            time_later,
+            time_later + Duration::minutes(25),
+        );
+        let route = desired(Duration::zero());
+        let mut connections = vec![
+            (&route, &later),
+            (&route, &with_transfers),
+            (&route, &direct),
+        ];
+        prefer_direct_connections(&mut connections, Duration::minutes(5));
+        assert_eq!(
+            connections,
+            vec![
+                (&route, &direct),
+                (&route, &with_transfers),
+                (&route, &later)
+            ]
+        );
+    }
+
+    #[test]
+    fn preferred_connection_by_local_departure_time() {
+        let mut route = desired(Duration::minutes(5));
+        route.preferred_times = vec![NaiveTime::from_hms_opt(17, 42, 0).unwrap()];
+        let connection_at = |hour, minute| {
+            let departure = Local
+                .with_ymd_and_hms(2024, 6, 1, hour, minute, 0)
+                .unwrap()
+                .fixed_offset();
+            Connection::single_part(
+                TransportType::UBahn,
+                "U6",
+                departure,
+                departure + Duration::minutes(20),
+            )
+        };
+        assert!(is_preferred_connection(&route, &connection_at(17, 42)));
+        assert!(!is_preferred_connection(&route, &connection_at(17, 43)));
+        assert!(!is_preferred_connection(&route, &connection_at(16, 42)));
+    }
+
+    #[test]
+    fn update_config_keeps_reordered_routes() {
+        let mut cache = populated_cache();
+        let mut other = desired(Duration::minutes(5));
+        other.start = "Hauptbahnhof".to_string();
+        cache.connections.push((other.clone(), Vec::new()));
+        let mut changed = cache.connections[0].0.clone();
+        changed.walk_to_start = Duration::minutes(10);
+
+        let reordered = cache.clone().update_config(Config {
+            connections: vec![other.clone(), cache.connections[0].0.clone()],
+            ..Config::default()
+        });
+        assert_eq!(
+            reordered.connections,
+            vec![cache.connections[1].clone(), cache.connections[0].clone()]
+        );
+
+        let updated = cache.update_config(Config {
+            connections: vec![changed.clone(), other.clone()],
+            ..Config::default()
+        });
+        assert_eq!(
+            updated.connections,
+            vec![(changed, Vec::new()), (other, Vec::new())]
+        );
+    }
+
+    #[test]
+    fn compact_drops_data_of_removed_routes() {
+        let mut cache = populated_cache();
+        let mut other = desired(Duration::minutes(5));
+        other.start = "Hauptbahnhof".to_string();
+        cache.connections.push((other.clone(), Vec::new()));
+        let keys = cache
+            .all_connections()
+            .iter()
+            .map(|(desired, c)| connection_key(desired, c))
+            .chain(["Hauptbahnhof→Destination 2024-06-01T17:32:00+02:00 UBAHN U6 2024-06-01T17:52:00+02:00".to_string()])
+            .collect::<Vec<_>>();
+        let mut cache = cache.with_shown(keys.clone());
+        cache.start_stations = HashMap::from([
+            ("Start→Destination".to_string(), "Start".to_string()),
+            (
+                "Hauptbahnhof→Destination".to_string(),
+                "Hauptbahnhof".to_string(),
+            ),
+        ]);
+
+        let config = Config {
+            connections: vec![other],
+            ..Config::default()
+        };
+        let compacted = cache.update_config(config).compact();
+        assert_eq!(compacted.shown, keys[2..].to_vec());
+        assert_eq!(
+            compacted.start_stations,
+            HashMap::from([(
+                "Hauptbahnhof→Destination".to_string(),
+                "Hauptbahnhof".to_string()
+            )])
+        );
+    }
+
+    #[test]
+    fn add_shown_connections_to_cached_shown_connections() {
+        let cache = populated_cache();
+        let keys = cache
+            .all_connections()
+            .iter()
+            .map(|(desired, c)| connection_key(desired, c))
+            .collect::<Vec<_>>();
+        let departed =
+            "Start→Destination 2024-06-01T17:02:00+02:00 UBAHN U6 2024-06-01T17:22:00+02:00";
+        let cache = cache
+            .with_shown(vec![departed.to_string(), keys[0].clone()])
+            .add_shown(vec![keys[1].clone(), keys[0].clone()]);
+        assert_eq!(cache.shown, keys);
+    }
+
+    #[test]
+    fn remember_shown_connections() {
+        let cache = populated_cache();
+        let keys = cache
+            .all_connections()
+            .iter()
+            .take(1)
+            .map(|(desired, c)| connection_key(desired, c))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "Start→Destination 2024-06-01T17:32:00+02:00 UBAHN U6 2024-06-01T17:52:00+02:00"
+                    .to_string()
+            ]
+        );
+        let cache = cache.clone().with_shown(keys);
+        let shown = cache
+            .all_connections()
+            .iter()
+            .map(|(desired, c)| cache.was_shown(desired, c))
+            .collect::<Vec<_>>();
+        assert_eq!(shown, vec![true, false]);
+        let contents = flexbuffers::to_vec(&cache).unwrap();
+        assert_eq!(ConnectionsCache::from_slice(&contents).unwrap(), cache);
+    }
+
+    #[test]
+    fn connections_in_order_merges_routes() {
+        let mut cache = cache_with_departures(
+            Duration::minutes(5),
+            &["2024-06-01T17:52:00+02:00", "2024-06-01T17:32:00+02:00"],
+        );
+        cache.connections.extend(
+            cache_with_departures(
+                Duration::minutes(10),
+                &["2024-06-01T17:47:00+02:00", "2024-06-01T17:37:00+02:00"],
+            )
+            .connections,
+        );
+        let first_two = cache
+            .connections_in_order()
+            .take(2)
+            .map(|(_, c)| c.planned_departure_time())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            first_two,
+            vec![
+                time("2024-06-01T17:32:00+02:00"),
+                time("2024-06-01T17:37:00+02:00")
+            ]
+        );
+        assert_eq!(
+            departures(&cache),
+            vec![
+                time("2024-06-01T17:32:00+02:00"),
+                time("2024-06-01T17:37:00+02:00"),
+                time("2024-06-01T17:47:00+02:00"),
+                time("2024-06-01T17:52:00+02:00")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_empty_records_start_station() {
+        let mut cache = populated_cache();
+        cache
+            .connections
+            .push((desired(Duration::minutes(5)), Vec::new()));
+        cache.connections[1].0.destination = "Elsewhere".to_string();
+        let cache = cache
+            .refresh_empty::<anyhow::Error, _, _>(
+                time("2024-06-01T17:30:00+02:00").with_timezone(&Utc),
+                |desired| async {
+                    let connections = Connection::from_fixture("connections_with_delays");
+                    Ok((desired, Station::new("de:09162:6", "Start"), connections))
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.start_stations,
+            HashMap::from([("Start→Elsewhere".to_string(), "Start".to_string())])
+        );
+        let (desired, connections) = &cache.connections[1];
+        // The fixture departs at "Hauptbahnhof", not at "Start"
+        assert!(cache.has_unexpected_start(desired, &connections[0]));
+        let (desired, connections) = &cache.connections[0];
+        assert!(!cache.has_unexpected_start(desired, &connections[0]));
+    }
+
+    #[tokio::test]
+    async fn refresh_empty_with_failing_route() {
+        let mut cache = populated_cache();
+        cache.connections[0].1.clear();
+        cache
+            .connections
+            .push((desired(Duration::minutes(5)), Vec::new()));
+        cache.connections[1].0.destination = "Elsewhere".to_string();
+        let refresh = |cache: ConnectionsCache| {
+            cache.refresh_empty(
+                time("2024-06-01T17:30:00+02:00").with_timezone(&Utc),
+                |desired| async {
+                    if desired.destination == "Elsewhere" {
+                        Err(anyhow!("No station named Elsewhere"))
+                    } else {
+                        let connections = Connection::from_fixture("connections_with_delays");
+                        Ok((desired, Station::new("de:09162:6", "Start"), connections))
+                    }
+                },
+            )
+        };
+        let refreshed = refresh(cache.clone()).await.unwrap();
+        assert_eq!(refreshed.connections[0].1.len(), 2);
+        assert!(refreshed.connections[1].1.is_empty());
+
+        // Succeed if the other route still has cached connections
+        let mut cached = cache.clone();
+        cached.connections[0].1 = populated_cache().connections[0].1.clone();
+        let refreshed = refresh(cached.clone()).await.unwrap();
+        assert_eq!(refreshed.connections[0].1, cached.connections[0].1);
+        assert!(refreshed.connections[1].1.is_empty());
+
+        // Fail if no route refreshes
+        cache.connections.remove(0);
+        let error = refresh(cache).await.unwrap_err();
+        assert_eq!(error.to_string(), "No station named Elsewhere");
+    }
+
+    #[test]
+    fn merge_disjoint_routes() {
+        let mut cache = cache_with_departures(Duration::minutes(5), &["2024-06-01T17:32:00+02:00"]);
+        cache
+            .start_stations
+            .insert("Start→Destination".to_string(), "Start".to_string());
+        let mut other = cache_with_departures(Duration::minutes(5), &["2024-06-01T17:42:00+02:00"]);
+        other.connections[0].0.destination = "Elsewhere".to_string();
+        other.shown = vec!["Start→Elsewhere key".to_string()];
+        let merged = cache.clone().merge(other.clone());
+        assert_eq!(
+            merged.connections,
+            vec![cache.connections[0].clone(), other.connections[0].clone()]
+        );
+        assert_eq!(merged.start_stations, cache.start_stations);
+        assert_eq!(merged.shown, other.shown);
+    }
+
+    #[test]
+    fn merge_overlapping_routes() {
+        let cache = cache_with_departures(
+            Duration::minutes(5),
+            &["2024-06-01T17:32:00+02:00", "2024-06-01T17:52:00+02:00"],
+        );
+        let mut other = cache_with_departures(
+            Duration::minutes(10),
+            &["2024-06-01T17:42:00+02:00", "2024-06-01T17:52:00+02:00"],
+        );
+        other.connections[0].1[1].parts[0] =
+            other.connections[0].1[1].parts[0].clone().delayed(2, 2);
+        let merged = cache.clone().merge(other.clone());
+        assert_eq!(merged.connections.len(), 1);
+        let (desired, connections) = &merged.connections[0];
+        assert_eq!(desired, &other.connections[0].0);
+        assert_eq!(
+            connections,
+            &vec![
+                cache.connections[0].1[0].clone(),
+                other.connections[0].1[0].clone(),
+                other.connections[0].1[1].clone(),
+            ]
+        );
+    }
+
+    #[test]
+    fn cache_path_by_config_files() {
+        assert_eq!(cache_path(&[]), cache_dir().join("connections"));
+        let path =
+            |files: &[&str]| cache_path(&files.iter().map(PathBuf::from).collect::<Vec<_>>());
+        let work = path(&["/etc/home/work.toml"]);
+        assert_eq!(work.parent(), Some(cache_dir().as_path()));
+        assert!(work
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("connections-"));
+        assert_eq!(work, path(&["/etc/home/work.toml"]));
+        assert_ne!(work, path(&["/etc/home/school.toml"]));
+        assert_ne!(
+            path(&["/etc/home/a.toml", "/etc/home/b.toml"]),
+            path(&["/etc/home/a.tomlb", "/etc/home/.toml"])
+        );
+    }
+
+    #[test]
+    fn prune_only_unusable_cache_files() {
+        let directory =
+            std::env::temp_dir().join(format!("home-prune-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let cache = cache_with_departures(Duration::minutes(5), &["2024-06-01T17:32:00+02:00"]);
+        cache.save(&directory.join("connections")).unwrap();
+        cache
+            .save(&directory.join("connections-work.json"))
+            .unwrap();
+        cache
+            .save(&last_good_path(&directory.join("connections-work.json")))
+            .unwrap();
+        std::fs::write(directory.join("connections-broken"), b"garbage").unwrap();
+        std::fs::write(directory.join("connections-last-good"), b"garbage").unwrap();
+
+        let files = cache_files_in(&directory).unwrap();
+        let removed = prune_cache_files_in(&directory).unwrap();
+        let remaining = cache_files_in(&directory).unwrap();
+        let last_good_exists = directory.join("connections-work-last-good.json").exists();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            files,
+            vec![
+                directory.join("connections"),
+                directory.join("connections-broken"),
+                directory.join("connections-work.json"),
+            ]
+        );
+        assert_eq!(removed, vec![(directory.join("connections-broken"), 7)]);
+        assert_eq!(
+            remaining,
+            vec![
+                directory.join("connections"),
+                directory.join("connections-work.json"),
+            ]
+        );
+        assert!(last_good_exists);
+    }
+
+    #[test]
+    fn last_good_path_next_to_cache() {
+        assert_eq!(
+            last_good_path(Path::new("/cache/connections")),
+            Path::new("/cache/connections-last-good")
+        );
+        assert_eq!(
+            last_good_path(Path::new("/tmp/cache.json")),
+            Path::new("/tmp/cache-last-good.json")
+        );
+    }
+
+    #[test]
+    fn fill_empty_routes_from_snapshot() {
+        let mut snapshot =
+            cache_with_departures(Duration::minutes(5), &["2024-06-01T17:32:00+02:00"]);
+        snapshot
+            .start_stations
+            .insert("Start→Destination".to_string(), "Start".to_string());
+        let empty = cache_with_departures(Duration::minutes(5), &[]);
+        let filled = empty.clone().fill_empty_from(snapshot.clone());
+        assert_eq!(filled.connections, snapshot.connections);
+        assert_eq!(filled.start_stations, snapshot.start_stations);
+
+        // Keep cached connections
+        let cache = cache_with_departures(Duration::minutes(5), &["2024-06-01T17:42:00+02:00"]);
+        let filled = cache.clone().fill_empty_from(snapshot.clone());
+        assert_eq!(filled.connections, cache.connections);
+
+        // Ignore snapshots of routes with different settings
+        let changed = cache_with_departures(Duration::minutes(10), &[]);
+        let filled = changed.clone().fill_empty_from(snapshot);
+        assert_eq!(filled.connections, changed.connections);
+    }
+
+    #[test]
+    fn group_by_departure_hour() {
+        // Build departures in local time, so that they fall into the same
+        // hours regardless of the offset of the local timezone.
+        let local = |hour, minute| {
+            Local
+                .with_ymd_and_hms(2024, 6, 1, hour, minute, 0)
+                .unwrap()
+                .fixed_offset()
+        };
+        let departures =
+            [local(17, 32), local(17, 52), local(18, 5)].map(|departure| departure.to_rfc3339());
+        let cache = cache_with_departures(
+            Duration::minutes(5),
+            &departures.each_ref().map(String::as_str),
+        );
+        let connections = cache.all_connections();
+        let hours = by_departure_hour(&connections)
+            .into_iter()
+            .map(|(hour, connections)| (hour, departures_of(&connections)))
+            .collect::<Vec<_>>();
+        let hour = |hour| {
+            NaiveDate::from_ymd_opt(2024, 6, 1)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap()
+        };
+        assert_eq!(
+            hours,
+            vec![
+                (hour(17), vec![local(17, 32), local(17, 52)]),
+                (hour(18), vec![local(18, 5)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn routes_with_all_connections() {
+        let mut cache = cache_with_departures(
+            Duration::minutes(5),
+            &["2024-06-01T17:32:00+02:00", "2024-06-01T17:42:00+02:00"],
+        );
+        cache.connections[0].0.ignore_starting_with = vec!["U6".to_string()];
+        let mut other = desired(Duration::minutes(10));
+        other.destination = "Elsewhere".to_string();
+        cache.connections.push((other.clone(), Vec::new()));
+        let routes = cache
+            .routes()
+            .map(|(desired, connections)| (desired.destination.as_str(), connections.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(routes, vec![("Destination", 2), ("Elsewhere", 0)]);
+        assert!(cache.all_connections().is_empty());
+    }
+
+    #[test]
+    fn explain_eviction() {
+        let times = [
+            "2024-06-01T17:32:00+02:00",
+            "2024-06-01T17:42:00+02:00",
+            "2024-06-01T17:52:00+02:00",
+        ];
+        let now = time("2024-06-01T17:30:00+02:00").with_timezone(&Utc);
+        let cache = cache_with_departures(Duration::minutes(10), &times);
+        let fates = cache
+            .explain_eviction(now, Reachability::Lenient)
+            .into_iter()
+            .map(|(_, c, fate)| (c.planned_departure_time(), fate))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fates,
+            vec![
+                (time(times[0]), Some(EvictionReason::Unreachable)),
+                (time(times[1]), Some(EvictionReason::TooFew)),
+                (time(times[2]), Some(EvictionReason::TooFew)),
+            ]
+        );
+
+        let now = time("2024-06-01T17:20:00+02:00").with_timezone(&Utc);
+        let fates = cache
+            .explain_eviction(now, Reachability::Lenient)
+            .into_iter()
+            .map(|(_, _, fate)| fate)
+            .collect::<Vec<_>>();
+        assert_eq!(fates, vec![None, None, None]);
+    }
+
+    #[test]
+    fn display_eviction_reasons() {
+        assert_eq!(EvictionReason::Unreachable.to_string(), "unreachable");
+        assert_eq!(EvictionReason::TooFew.to_string(), "too-few");
+        assert_eq!(
+            EvictionReason::PedestrianStart.to_string(),
+            "pedestrian-start"
+        );
+    }
+
+    #[tokio::test]
+    async fn reuse_connections_requested_for_same_minute() {
+        let mut cache = populated_cache();
+        cache.connections[0].1.clear();
+        let requested = time("2024-06-01T17:30:40+02:00").with_timezone(&Utc);
+        let cache = cache
+            .refresh_empty::<anyhow::Error, _, _>(requested, |desired| async {
+                let connections = Connection::from_fixture("connections_with_delays");
+                Ok((desired, Station::new("de:09162:6", "Start"), connections))
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.start_times,
+            HashMap::from([(
+                "Start→Destination".to_string(),
+                time("2024-06-01T17:30:00+02:00").with_timezone(&Utc)
+            )])
+        );
+        let remaining = |start: &str| {
+            cache
+                .clone()
+                .evict_requested_after(time(start).with_timezone(&Utc))
+                .all_connections()
+                .len()
+        };
+        assert_eq!(remaining("2024-06-01T17:30:10+02:00"), 2);
+        assert_eq!(remaining("2024-06-01T17:35:00+02:00"), 2);
+        assert_eq!(remaining("2024-06-01T17:29:59+02:00"), 0);
+    }
+
+    #[test]
+    fn lacks_realtime_information() {
+        assert!(!ConnectionsCache::default().lacks_realtime_information(|_| true));
+        let cache = cache_with_departures(
+            Duration::minutes(5),
+            &["2024-06-01T17:32:00+02:00", "2024-06-01T17:42:00+02:00"],
+        );
+        assert!(cache.lacks_realtime_information(|_| true));
+        assert!(!cache.lacks_realtime_information(|_| false));
+        let mut cache = cache;
+        cache.connections[0]
+            .1
+            .extend(Connection::from_fixture("connections_with_delays"));
+        assert!(!cache.lacks_realtime_information(|_| true));
+    }
+
+    #[test]
+    fn lacks_realtime_information_of_matching_routes_only() {
+        let mut cache = cache_with_departures(
+            Duration::minutes(5),
+            &["2024-06-01T17:32:00+02:00", "2024-06-01T17:42:00+02:00"],
+        );
+        let mut refreshed = desired(Duration::minutes(5));
+        refreshed.destination = "Elsewhere".to_string();
+        cache.connections.push((
+            refreshed,
+            Connection::from_fixture("connections_with_delays"),
+        ));
+        assert!(!cache.lacks_realtime_information(|_| true));
+        assert!(cache.lacks_realtime_information(|desired| desired.destination == "Destination"));
+        assert!(!cache.lacks_realtime_information(|desired| desired.destination == "Elsewhere"));
+    }
+
+    #[test]
+    fn routes_too_far_to_walk() {
+        let cache = cache_with_departures(
+            Duration::minutes(30),
+            &["2024-06-01T17:42:00+02:00", "2024-06-01T17:52:00+02:00"],
+        );
+        let now = |s| time(s).with_timezone(&Utc);
+        let too_far = |now| {
+            cache
+                .routes_too_far_to_walk(now, Reachability::Lenient)
+                .len()
+        };
+        // Both connections leave 15 minutes to walk
+        assert_eq!(too_far(now("2024-06-01T17:37:00+02:00")), 0);
+        assert_eq!(too_far(now("2024-06-01T17:38:00+02:00")), 1);
+        // One connection departed already
+        assert_eq!(too_far(now("2024-06-01T17:43:00+02:00")), 0);
+        assert!(cache
+            .routes_too_far_to_walk(now("2024-06-01T17:20:00+02:00"), Reachability::Strict)
+            .is_empty());
+    }
+
+    #[test]
+    fn actual_leave_first() {
+        let route = desired(Duration::minutes(5));
+        let connection = |departure, delay| {
+            let departure = time(departure);
+            Connection::from_parts(vec![ConnectionPart::new(
+                TransportType::Bus,
+                "150",
+                departure,
+                departure + Duration::minutes(10),
+            )
+            .delayed(delay, delay)])
+        };
+        let late = connection("2024-06-01T17:42:00+02:00", 8);
+        let on_time = connection("2024-06-01T17:45:00+02:00", 0);
+        let mut connections = vec![(&route, &late), (&route, &on_time)];
+        super::actual_leave_first(&mut connections);
+        assert_eq!(connections, vec![(&route, &on_time), (&route, &late)]);
+    }
+
+    #[test]
+    fn avoid_transfer_stops() {
+        let departure = time("2024-06-01T17:42:00+02:00");
+        let via = |stop| {
+            Connection::from_parts(vec![
+                ConnectionPart::new(
+                    TransportType::UBahn,
+                    "U4",
+                    departure,
+                    departure + Duration::minutes(7),
+                )
+                .between("Start", stop),
+                ConnectionPart::new(
+                    TransportType::Tram,
+                    "19",
+                    departure + Duration::minutes(9),
+                    departure + Duration::minutes(15),
+                )
+                .between(stop, "Destination"),
+            ])
+        };
+        let mut route = desired(Duration::minutes(5));
+        route.avoid_stops = vec!["Max-Weber-Platz".to_string(), "Start".to_string()];
+        let cache = ConnectionsCache {
+            connections: vec![(
+                route,
+                vec![
+                    via("Max-Weber-Platz"),
+                    via("max-weber-platz"),
+                    via("Odeonsplatz"),
+                ],
+            )],
+            ..ConnectionsCache::default()
+        };
+        let transfers = cache
+            .all_connections()
+            .into_iter()
+            .map(|(_, c)| c.departure().to().name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(transfers, vec!["max-weber-platz", "Odeonsplatz"]);
+    }
+
+    #[test]
+    fn latest_leave_first() {
+        let route = |walk_to_start| desired(Duration::minutes(walk_to_start));
+        let connection = |departure, arrival| {
+            Connection::single_part(TransportType::Bus, "150", time(departure), time(arrival))
+        };
+        let (near, far) = (route(2), route(10));
+        let (a, b, c) = (
+            connection("2024-06-01T17:42:00+02:00", "2024-06-01T17:50:00+02:00"),
+            connection("2024-06-01T17:46:00+02:00", "2024-06-01T17:55:00+02:00"),
+            connection("2024-06-01T17:52:00+02:00", "2024-06-01T18:00:00+02:00"),
+        );
+        // Leave at 17:40, 17:36, and 17:50
+        let mut connections = vec![(&near, &a), (&far, &b), (&near, &c)];
+        super::latest_leave_first(&mut connections);
+        assert_eq!(connections, vec![(&near, &c), (&near, &a), (&far, &b)]);
+    }
+
+    #[test]
+    fn keep_connections_with_same_departure_and_different_arrival() {
+        let departure = time("2024-06-01T17:42:00+02:00");
+        let connection = |minutes| {
+            Connection::single_part(
+                TransportType::Bus,
+                "150",
+                departure,
+                departure + Duration::minutes(minutes),
+            )
+        };
+        let arrivals = |connections: Vec<Connection>| {
+            let cache = ConnectionsCache {
+                connections: vec![(desired(Duration::minutes(5)), connections)],
+                ..ConnectionsCache::default()
+            }
+            .evict_adjacent_stop_walks();
+            cache
+                .all_connections()
+                .iter()
+                .map(|(_, c)| c.planned_arrival_time())
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            departure + Duration::minutes(20),
+            departure + Duration::minutes(30),
+        ];
+        assert_eq!(arrivals(vec![connection(20), connection(30)]), expected);
+        assert_eq!(arrivals(vec![connection(30), connection(20)]), expected);
+    }
+
+    #[test]
+    fn evict_routes_matching() {
+        let mut cache = cache_with_departures(
+            Duration::minutes(5),
+            &["2024-06-01T17:42:00+02:00", "2024-06-01T17:52:00+02:00"],
+        );
+        cache.connections.extend(
+            cache_with_departures(Duration::minutes(5), &["2024-06-01T17:50:00+02:00"]).connections,
+        );
+        cache.connections[1].0.start = "Hauptbahnhof".to_string();
+        let cache = cache.evict_routes_matching("hauptbahnhof → dest");
+        assert_eq!(cache.connections[0].1.len(), 2);
+        assert!(cache.connections[1].1.is_empty());
+    }
+
+    #[test]
+    fn next_local_time_today_or_tomorrow() {
+        let now = Local.with_ymd_and_hms(2024, 6, 1, 17, 42, 0).unwrap();
+        let at = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert_eq!(next_local_time(now, at(17, 42)), now);
+        assert_eq!(
+            next_local_time(now, at(23, 0)),
+            Local.with_ymd_and_hms(2024, 6, 1, 23, 0, 0).unwrap()
+        );
+        assert_eq!(
+            next_local_time(now, at(1, 30)),
+            Local.with_ymd_and_hms(2024, 6, 2, 1, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn evict_routes_departing_at() {
+        let departure = Local
+            .with_ymd_and_hms(2024, 6, 1, 17, 42, 0)
+            .unwrap()
+            .fixed_offset();
+        let mut cache = cache_with_departures(Duration::minutes(5), &[]);
+        cache.connections[0].1.push(Connection::single_part(
+            TransportType::UBahn,
+            "U6",
+            departure,
+            departure + Duration::minutes(20),
+        ));
+        cache.connections.extend(
+            cache_with_departures(Duration::minutes(5), &["2024-06-01T17:50:00+02:00"]).connections,
+        );
+        let cache = cache.evict_routes_departing_at(NaiveTime::from_hms_opt(17, 42, 0).unwrap());
+        assert!(cache.connections[0].1.is_empty());
+        assert_eq!(cache.connections[1].1.len(), 1);
     }
 }
@@ -4,18 +4,62 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
-use chrono::Duration;
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Weekday};
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
 
+use crate::mvg::Coordinates;
+
 /// The configuration file.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// Only show connections which leave the full time to walk to the start.
+    #[serde(default)]
+    pub strict_reachable: bool,
+    /// Where home is, to show the distance to start stations.
+    #[serde(default)]
+    pub home: Option<Coordinates>,
+    /// Hide connections which walk longer than this between lines, in total.
+    #[serde(default, with = "optional_human_readable_duration")]
+    pub max_total_walk: Option<Duration>,
+    /// Round the time to walk to start stations to whole minutes.
+    #[serde(default)]
+    pub round_walk_to_start: Option<WalkRounding>,
+    /// The proxy to use for the MVG API, instead of looking up a proxy.
+    #[serde(default, with = "optional_url")]
+    pub proxy: Option<Url>,
+    /// Connect to the MVG API directly; takes precedence over `proxy`.
+    #[serde(default)]
+    pub no_proxy: bool,
     pub connections: Vec<DesiredConnection>,
 }
 
+/// How to round times to walk to start stations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalkRounding {
+    /// Round up to the next whole minute.
+    Up,
+    /// Round to the nearest whole minute, and half minutes up.
+    Nearest,
+}
+
+impl WalkRounding {
+    /// Round `duration` to whole minutes.
+    pub fn round(self, duration: Duration) -> Duration {
+        let seconds = duration.num_seconds();
+        let minutes = match self {
+            WalkRounding::Up => (seconds + 59).div_euclid(60),
+            WalkRounding::Nearest => (seconds + 30).div_euclid(60),
+        };
+        Duration::minutes(minutes)
+    }
+}
+
 mod human_readable_duration {
     use chrono::Duration;
     use serde::de::Unexpected;
@@ -57,8 +101,59 @@ mod human_readable_duration {
     }
 }
 
+mod optional_human_readable_duration {
+    use chrono::Duration;
+    use serde::{Deserialize, Serialize};
+    use serde::{Deserializer, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct HumanReadableDuration(#[serde(with = "super::human_readable_duration")] Duration);
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Option::<HumanReadableDuration>::deserialize(deserializer)?.map(|d| d.0))
+    }
+
+    pub fn serialize<S>(value: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        value.map(HumanReadableDuration).serialize(serializer)
+    }
+}
+
+mod optional_url {
+    use reqwest::Url;
+    use serde::de::Unexpected;
+    use serde::{de, Deserialize, Serialize};
+    use serde::{Deserializer, Serializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Url>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| {
+                Url::parse(&value).map_err(|err| {
+                    de::Error::invalid_value(Unexpected::Str(&value), &format!("{}", err).as_str())
+                })
+            })
+            .transpose()
+    }
+
+    pub fn serialize<S>(value: &Option<Url>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        value.as_ref().map(Url::as_str).serialize(serializer)
+    }
+}
+
 /// A desired connection in the config file
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct DesiredConnection {
     /// The name of the start station.
     pub start: String,
@@ -70,6 +165,141 @@ pub struct DesiredConnection {
     /// A list of product labels (e.g. S2, 12, 947) to ignore
     #[serde(default)]
     pub ignore_starting_with: Vec<String>,
+    /// A list of departure times (e.g. 17:42) to highlight
+    #[serde(default)]
+    pub preferred_times: Vec<NaiveTime>,
+    /// Days of the week (e.g. Mon, Tue) this route is active on; all days if empty
+    #[serde(default)]
+    pub active_days: Vec<Weekday>,
+    /// Hours this route is active at; all day if absent
+    #[serde(default)]
+    pub active_hours: Option<ActiveHours>,
+    /// A stop to continue to from the destination, to show the onward connection
+    #[serde(default)]
+    pub onward: Option<String>,
+    /// A list of stops to not change lines at; names match exactly, including case
+    #[serde(default)]
+    pub avoid_stops: Vec<String>,
+    /// A color to tint connections of this route with, to tell routes apart
+    #[serde(default)]
+    pub color: Option<RouteColor>,
+}
+
+/// A color to tint connections of a route with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+/// A window of time of a day.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActiveHours {
+    /// The start of the window.
+    pub from: NaiveTime,
+    /// The end of the window, exclusive.
+    ///
+    /// If before `from` the window spans midnight.
+    pub to: NaiveTime,
+}
+
+impl ActiveHours {
+    /// Whether `time` is within this window.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            self.from <= time || time < self.to
+        }
+    }
+}
+
+/// Expand every `${VAR}` in `value` with the value `lookup` returns for `VAR`.
+fn expand_variables<F>(value: &str, lookup: F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let variable = &rest[start + 2..];
+        let end = variable
+            .find('}')
+            .with_context(|| format!("Unterminated variable in {}", value))?;
+        let name = &variable[..end];
+        let variable_value =
+            lookup(name).with_context(|| format!("Undefined environment variable {}", name))?;
+        expanded.push_str(&variable_value);
+        rest = &variable[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+impl DesiredConnection {
+    /// A stable identifier of this route.
+    ///
+    /// The identifier depends only on start and destination, and thus does
+    /// not change when reordering routes in the configuration.
+    pub fn id(&self) -> String {
+        format!("{}→{}", self.start, self.destination)
+    }
+
+    /// Whether this route is active at the given local `time`.
+    pub fn is_active_at(&self, time: NaiveDateTime) -> bool {
+        (self.active_days.is_empty() || self.active_days.contains(&time.weekday()))
+            && self
+                .active_hours
+                .is_none_or(|hours| hours.contains(time.time()))
+    }
+
+    /// Whether this route matches `pattern`.
+    ///
+    /// A pattern of the form `start→destination` (or `start->destination`)
+    /// matches if the start contains `start` and the destination contains
+    /// `destination`; any other pattern matches if either the start or the
+    /// destination contains it.  Matching ignores case.
+    pub fn matches_route(&self, pattern: &str) -> bool {
+        let contains =
+            |name: &str, part: &str| name.to_lowercase().contains(&part.trim().to_lowercase());
+        match pattern.split_once('→').or_else(|| pattern.split_once("->")) {
+            Some((start, destination)) => {
+                contains(&self.start, start) && contains(&self.destination, destination)
+            }
+            None => contains(&self.start, pattern) || contains(&self.destination, pattern),
+        }
+    }
+}
+
+/// Add `connection` to `connections`, unless an identical route already exists.
+///
+/// Fail if `connections` has a different route with the same
+/// [`DesiredConnection::id`].
+fn add_route(
+    connections: &mut Vec<DesiredConnection>,
+    connection: DesiredConnection,
+) -> Result<()> {
+    match connections.iter().find(|c| c.id() == connection.id()) {
+        Some(existing) if *existing == connection => Ok(()),
+        Some(_) => Err(anyhow!(
+            "Conflicting connections from {} to {}",
+            connection.start,
+            connection.destination
+        )),
+        None => {
+            connections.push(connection);
+            Ok(())
+        }
+    }
 }
 
 impl Config {
@@ -86,21 +316,540 @@ impl Config {
                 path.as_ref().display()
             )
         })?;
-        toml::from_str(contents).with_context(|| {
+        let config: Self = toml::from_str(contents).with_context(|| {
             format!(
                 "Failed to parse configuration from {}",
                 path.as_ref().display()
             )
+        })?;
+        config
+            .expand_variables(|name| std::env::var(name).ok())
+            .with_context(|| {
+                format!(
+                    "Failed to expand variables in configuration from {}",
+                    path.as_ref().display()
+                )
+            })?
+            .dedup_routes()
+            .with_context(|| {
+                format!(
+                    "Duplicate routes in configuration from {}",
+                    path.as_ref().display()
+                )
+            })
+    }
+
+    /// Remove routes which occur more than once, by [`DesiredConnection::id`].
+    ///
+    /// Keep the first of identical routes, and fail if two routes have the
+    /// same start and destination, but differ otherwise, e.g. in the time
+    /// to walk to the start, like [`Self::merge`] does.
+    fn dedup_routes(self) -> Result<Self> {
+        let mut connections: Vec<DesiredConnection> = Vec::with_capacity(self.connections.len());
+        for connection in self.connections {
+            add_route(&mut connections, connection)?;
+        }
+        Ok(Self {
+            connections,
+            ..self
         })
     }
 
+    /// Expand `${VAR}` in start and destination of all connections.
+    ///
+    /// Look up the value of variables with `lookup`.
+    fn expand_variables<F>(self, lookup: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let connections = self
+            .connections
+            .into_iter()
+            .map(|connection| {
+                Ok(DesiredConnection {
+                    start: expand_variables(&connection.start, &lookup)?,
+                    destination: expand_variables(&connection.destination, &lookup)?,
+                    ..connection
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            connections,
+            ..self
+        })
+    }
+
+    /// Write this configuration to the file at `path`.
+    ///
+    /// This is the inverse of [`Self::from_file`], but does not preserve
+    /// comments, the original layout, or `${VAR}` references.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let contents = toml::to_string_pretty(self)
+            .with_context(|| "Failed to serialize configuration".to_string())?;
+        std::fs::write(path.as_ref(), contents).with_context(|| {
+            format!(
+                "Failed to write configuration file to {}",
+                path.as_ref().display()
+            )
+        })
+    }
+
+    /// Load configuration from all `paths` and merge them in order.
+    pub fn from_files<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        paths.iter().try_fold(Self::default(), |config, path| {
+            config.merge(Self::from_file(path)?).with_context(|| {
+                format!(
+                    "Failed to merge configuration from {}",
+                    path.as_ref().display()
+                )
+            })
+        })
+    }
+
+    /// Merge the connections of `other` into this configuration.
+    ///
+    /// Skip connections of `other` which already exist in this configuration,
+    /// and fail if `other` has a different connection with the same start
+    /// and destination.
+    pub fn merge(mut self, other: Config) -> Result<Self> {
+        for connection in other.connections {
+            add_route(&mut self.connections, connection)?;
+        }
+        self.home = match (self.home, other.home) {
+            (Some(home), Some(other_home)) if home != other_home => {
+                return Err(anyhow!("Conflicting home coordinates"))
+            }
+            (home, other_home) => home.or(other_home),
+        };
+        self.max_total_walk = match (self.max_total_walk, other.max_total_walk) {
+            (Some(walk), Some(other_walk)) => Some(walk.min(other_walk)),
+            (walk, other_walk) => walk.or(other_walk),
+        };
+        self.round_walk_to_start = match (self.round_walk_to_start, other.round_walk_to_start) {
+            (Some(rounding), Some(other_rounding)) if rounding != other_rounding => {
+                return Err(anyhow!("Conflicting rounding of walk times"))
+            }
+            (rounding, other_rounding) => rounding.or(other_rounding),
+        };
+        self.proxy = match (self.proxy, other.proxy) {
+            (Some(proxy), Some(other_proxy)) if proxy != other_proxy => {
+                return Err(anyhow!("Conflicting proxies"))
+            }
+            (proxy, other_proxy) => proxy.or(other_proxy),
+        };
+        self.no_proxy |= other.no_proxy;
+        self.strict_reachable |= other.strict_reachable;
+        Ok(self)
+    }
+
+    /// Round the time to walk to the start of every route, as configured.
+    ///
+    /// Leave times and displayed walk times then use the same, rounded time.
+    pub fn round_walk_to_start(self) -> Self {
+        match self.round_walk_to_start {
+            None => self,
+            Some(rounding) => Self {
+                connections: self
+                    .connections
+                    .into_iter()
+                    .map(|desired| DesiredConnection {
+                        walk_to_start: rounding.round(desired.walk_to_start),
+                        ..desired
+                    })
+                    .collect(),
+                ..self
+            },
+        }
+    }
+
+    /// The path of the configuration file at the default location.
+    ///
+    /// The file need not exist.
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(dirs::config_dir()
+            .with_context(|| "Missing HOME directory".to_string())?
+            .join("de.swsnr.home")
+            .join("home.toml"))
+    }
+
     /// Load config from `$XDG_CONFIG_HOME`.
     pub fn from_default_location() -> Result<Self> {
-        Self::from_file(
-            dirs::config_dir()
-                .with_context(|| "Missing HOME directory".to_string())?
-                .join("de.swsnr.home")
-                .join("home.toml"),
+        Self::from_file(Self::default_path()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::*;
+    use pretty_assertions::assert_eq;
+
+    fn config(s: &str) -> Config {
+        toml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn merge_disjoint_configs() {
+        let merged = config(
+            r#"[[connections]]
+start = "Hauptbahnhof"
+destination = "Ostbahnhof München"
+walk_to_start = "10min"
+"#,
         )
+        .merge(config(
+            r#"[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "5min"
+"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            merged
+                .connections
+                .iter()
+                .map(|c| c.start.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Hauptbahnhof", "Waldfriedhof"]
+        );
+    }
+
+    #[test]
+    fn merge_identical_connections() {
+        let s = r#"[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "5min"
+"#;
+        assert_eq!(config(s).merge(config(s)).unwrap(), config(s));
+    }
+
+    #[test]
+    fn merge_conflicting_connections() {
+        let error = config(
+            r#"[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "5min"
+"#,
+        )
+        .merge(config(
+            r#"[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "10min"
+"#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Conflicting connections from Waldfriedhof to Schwanthaler Höhe"
+        );
+    }
+
+    #[test]
+    fn merge_home_coordinates() {
+        let home = config(
+            r#"connections = []
+home = { latitude = 48.1, longitude = 11.5 }
+"#,
+        );
+        let merged = config("connections = []").merge(home.clone()).unwrap();
+        assert_eq!(merged.home, home.home);
+        let other_home = config(
+            r#"connections = []
+home = { latitude = 48.2, longitude = 11.5 }
+"#,
+        );
+        let error = home.merge(other_home).unwrap_err();
+        assert_eq!(error.to_string(), "Conflicting home coordinates");
+    }
+
+    #[test]
+    fn merge_max_total_walk() {
+        let walk = |minutes| {
+            config(&format!(
+                "connections = []\nmax_total_walk = \"{}min\"\n",
+                minutes
+            ))
+        };
+        assert_eq!(walk(5).max_total_walk, Some(Duration::minutes(5)));
+        assert_eq!(config("connections = []").max_total_walk, None);
+        let merged = walk(10).merge(walk(5)).unwrap();
+        assert_eq!(merged.max_total_walk, Some(Duration::minutes(5)));
+        let merged = config("connections = []").merge(walk(10)).unwrap();
+        assert_eq!(merged.max_total_walk, Some(Duration::minutes(10)));
+    }
+
+    #[test]
+    fn round_walk_times() {
+        let walk = |minutes, seconds| Duration::minutes(minutes) + Duration::seconds(seconds);
+        for (duration, up, nearest) in [
+            (walk(0, 0), 0, 0),
+            (walk(3, 0), 3, 3),
+            (walk(3, 1), 4, 3),
+            (walk(3, 29), 4, 3),
+            (walk(3, 30), 4, 4),
+            (walk(3, 59), 4, 4),
+        ] {
+            assert_eq!(WalkRounding::Up.round(duration), Duration::minutes(up));
+            assert_eq!(
+                WalkRounding::Nearest.round(duration),
+                Duration::minutes(nearest)
+            );
+        }
+    }
+
+    #[test]
+    fn round_walk_to_start_of_routes() {
+        let route = r#"
+[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "3min 20s"
+"#;
+        let walk = |config: Config| config.round_walk_to_start().connections[0].walk_to_start;
+        assert_eq!(
+            walk(config(route)),
+            Duration::minutes(3) + Duration::seconds(20)
+        );
+        let rounded = format!("round_walk_to_start = \"up\"\n{route}");
+        assert_eq!(walk(config(&rounded)), Duration::minutes(4));
+        let rounded = format!("round_walk_to_start = \"nearest\"\n{route}");
+        assert_eq!(walk(config(&rounded)), Duration::minutes(3));
+        assert!(config("connections = []\nround_walk_to_start = \"up\"")
+            .merge(config(
+                "connections = []\nround_walk_to_start = \"nearest\""
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn write_and_read_config() {
+        let config = config(
+            r#"home = { latitude = 48.1374, longitude = 11.5755 }
+max_total_walk = "10min"
+round_walk_to_start = "up"
+
+[[connections]]
+start = "Hauptbahnhof"
+destination = "Ostbahnhof München"
+walk_to_start = "10min"
+ignore_starting_with = ["U5"]
+active_days = ["Mon", "Fri"]
+active_hours = { from = "06:00", to = "10:00" }
+
+[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "5min 30s"
+preferred_times = ["17:42"]
+onward = "Odeonsplatz"
+"#,
+        );
+        let path = std::env::temp_dir().join(format!("home-config-{}.toml", std::process::id()));
+        config.to_file(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let written = Config::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            contents.contains("walk_to_start = \"5m 30s\""),
+            "Unexpected contents: {contents}"
+        );
+        assert_eq!(written, config);
+    }
+
+    #[test]
+    fn duplicate_routes() {
+        let identical = config(
+            r#"[[connections]]
+start = "Hauptbahnhof"
+destination = "Ostbahnhof München"
+walk_to_start = "10min"
+
+[[connections]]
+start = "Hauptbahnhof"
+destination = "Ostbahnhof München"
+walk_to_start = "10min"
+"#,
+        )
+        .dedup_routes()
+        .unwrap();
+        assert_eq!(identical.connections.len(), 1);
+
+        let error = config(
+            r#"[[connections]]
+start = "Hauptbahnhof"
+destination = "Ostbahnhof München"
+walk_to_start = "10min"
+
+[[connections]]
+start = "Hauptbahnhof"
+destination = "Ostbahnhof München"
+walk_to_start = "5min"
+"#,
+        )
+        .dedup_routes()
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Conflicting connections from Hauptbahnhof to Ostbahnhof München"
+        );
+    }
+
+    #[test]
+    fn proxy() {
+        let config = config(
+            r#"proxy = "http://proxy.example.com:3128"
+connections = []
+"#,
+        );
+        assert_eq!(
+            config.proxy,
+            Some(Url::parse("http://proxy.example.com:3128").unwrap())
+        );
+        assert!(!config.no_proxy);
+        assert!(toml::from_str::<Config>("proxy = \"no url\"\nconnections = []").is_err());
+    }
+
+    #[test]
+    fn merge_conflicting_proxies() {
+        let proxy = |url: &str| Config {
+            proxy: Some(Url::parse(url).unwrap()),
+            ..Config::default()
+        };
+        let merged = proxy("http://proxy.example.com:3128")
+            .merge(proxy("http://proxy.example.com:3128"))
+            .unwrap();
+        assert_eq!(merged.proxy, proxy("http://proxy.example.com:3128").proxy);
+        assert!(proxy("http://proxy.example.com:3128")
+            .merge(proxy("http://other.example.com:3128"))
+            .is_err());
+    }
+
+    #[test]
+    fn route_color() {
+        let config = config(
+            r#"[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "5min"
+color = "magenta"
+"#,
+        );
+        assert_eq!(config.connections[0].color, Some(RouteColor::Magenta));
+    }
+
+    #[test]
+    fn reject_unknown_keys() {
+        let error = toml::from_str::<Config>(
+            r#"[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "5min"
+ignore_starts_with = ["U5"]
+"#,
+        )
+        .unwrap_err();
+        let message = error.to_string();
+        assert!(
+            message.contains("unknown field `ignore_starts_with`"),
+            "Unexpected error: {}",
+            message
+        );
+        assert!(message.contains("line 5"), "Unexpected error: {}", message);
+    }
+
+    #[test]
+    fn route_id_ignores_walk_time() {
+        let config = config(
+            r#"[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "5min"
+
+[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "10min"
+"#,
+        );
+        assert_eq!(config.connections[0].id(), "Waldfriedhof→Schwanthaler Höhe");
+        assert_eq!(config.connections[0].id(), config.connections[1].id());
+    }
+
+    #[test]
+    fn active_routes() {
+        let config = config(
+            r#"[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "5min"
+
+[[connections]]
+start = "Waldfriedhof"
+destination = "Hauptbahnhof"
+walk_to_start = "5min"
+active_days = ["Sat", "Sun"]
+active_hours = { from = "22:00", to = "02:00" }
+"#,
+        );
+        let at = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        let [always, weekend_nights] = &config.connections[..] else {
+            panic!("Unexpected connections: {:?}", config.connections)
+        };
+        // 2024-06-01 is a Saturday
+        assert!(always.is_active_at(at("2024-06-03 12:00")));
+        assert!(weekend_nights.is_active_at(at("2024-06-01 23:00")));
+        assert!(weekend_nights.is_active_at(at("2024-06-02 01:59")));
+        assert!(!weekend_nights.is_active_at(at("2024-06-02 02:00")));
+        assert!(!weekend_nights.is_active_at(at("2024-06-01 12:00")));
+        assert!(!weekend_nights.is_active_at(at("2024-06-03 23:00")));
+    }
+
+    #[test]
+    fn expand_variables_in_stations() {
+        let lookup = |name: &str| (name == "HOME").then(|| "Waldfriedhof".to_string());
+        let expanded = config(
+            r#"[[connections]]
+start = "Hauptbahnhof"
+destination = "${HOME}"
+walk_to_start = "10min"
+"#,
+        )
+        .expand_variables(lookup)
+        .unwrap();
+        assert_eq!(expanded.connections[0].destination, "Waldfriedhof");
+        assert_eq!(
+            expand_variables("${HOME} Nord, ${HOME}", lookup).unwrap(),
+            "Waldfriedhof Nord, Waldfriedhof"
+        );
+        assert_eq!(expand_variables("$HOME", lookup).unwrap(), "$HOME");
+        assert_eq!(
+            expand_variables("${WORK}", lookup).unwrap_err().to_string(),
+            "Undefined environment variable WORK"
+        );
+        assert_eq!(
+            expand_variables("${HOME", lookup).unwrap_err().to_string(),
+            "Unterminated variable in ${HOME"
+        );
+    }
+
+    #[test]
+    fn match_routes() {
+        let config = config(
+            r#"[[connections]]
+start = "Waldfriedhof"
+destination = "Schwanthaler Höhe"
+walk_to_start = "5min"
+"#,
+        );
+        let route = &config.connections[0];
+        assert!(route.matches_route("waldfried"));
+        assert!(route.matches_route("Höhe"));
+        assert!(route.matches_route("Waldfriedhof → Schwanthaler"));
+        assert!(route.matches_route("wald->höhe"));
+        assert!(!route.matches_route("Höhe → Waldfriedhof"));
+        assert!(!route.matches_route("Hauptbahnhof"));
     }
 }
@@ -0,0 +1,1430 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fmt::{Display, Formatter};
+
+use anstyle::{AnsiColor, Style};
+use anyhow::{anyhow, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Duration, FixedOffset, Local, Utc};
+
+use crate::config::RouteColor;
+use crate::mvg::{Connection, Coordinates, Place, TransportType};
+
+/// A single leg of a connection, with its own real time information.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leg<'a> {
+    /// The actual departure time of this leg, including delays.
+    pub departure_time: DateTime<FixedOffset>,
+    /// The departure delay, if real time information is available.
+    pub departure_delay: Option<Duration>,
+    /// The name of the stop this leg departs at.
+    pub departure_stop_name: &'a str,
+    /// The actual arrival time of this leg, including delays.
+    pub arrival_time: DateTime<FixedOffset>,
+    /// The arrival delay, if real time information is available.
+    pub arrival_delay: Option<Duration>,
+    /// The name of the stop this leg arrives at.
+    pub arrival_stop_name: &'a str,
+    /// The transport type of the line of this leg.
+    pub transport_type: TransportType,
+    /// The label of the line of this leg.
+    pub line_label: &'a str,
+    /// The names of the stops between departure and arrival; empty if unknown.
+    pub intermediate_stop_names: Vec<&'a str>,
+}
+
+/// A connection which we can display.
+pub trait DisplayableConnection {
+    /// The actual departure time, including delays.
+    fn departure_time(&self) -> DateTime<FixedOffset>;
+
+    /// The departure delay, if real time information is available.
+    fn departure_delay(&self) -> Option<Duration>;
+
+    /// The actual arrival time, including delays.
+    fn arrival_time(&self) -> DateTime<FixedOffset>;
+
+    /// The arrival delay, if real time information is available.
+    fn arrival_delay(&self) -> Option<Duration>;
+
+    /// The name of the stop this connection departs at.
+    fn departure_stop_name(&self) -> &str;
+
+    /// The coordinates of the stop this connection departs at, if known.
+    fn departure_stop_coordinates(&self) -> Option<Coordinates>;
+
+    /// The transport type of the first line of this connection.
+    fn transport_type(&self) -> TransportType;
+
+    /// The label of the first line of this connection.
+    fn line_label(&self) -> &str;
+
+    /// The name of the stop to leave the first line at.
+    ///
+    /// `None` if the connection has only a single part.
+    fn transfer_stop_name(&self) -> Option<&str>;
+
+    /// Whether this connection runs on demand only, and requires booking ahead.
+    fn is_on_demand(&self) -> bool;
+
+    /// Whether any part of this connection is cancelled.
+    fn is_cancelled(&self) -> bool;
+
+    /// All legs of this connection.
+    fn legs(&self) -> Vec<Leg<'_>>;
+}
+
+impl DisplayableConnection for Connection {
+    fn departure_time(&self) -> DateTime<FixedOffset> {
+        self.actual_departure_time()
+    }
+
+    fn departure_delay(&self) -> Option<Duration> {
+        Connection::departure_delay(self)
+    }
+
+    fn arrival_time(&self) -> DateTime<FixedOffset> {
+        self.actual_arrival_time()
+    }
+
+    fn arrival_delay(&self) -> Option<Duration> {
+        Connection::arrival_delay(self)
+    }
+
+    fn departure_stop_name(&self) -> &str {
+        self.departure().from().name()
+    }
+
+    fn departure_stop_coordinates(&self) -> Option<Coordinates> {
+        self.departure().from().coordinates()
+    }
+
+    fn transport_type(&self) -> TransportType {
+        self.departure().line_transport_type()
+    }
+
+    fn line_label(&self) -> &str {
+        self.departure().line_label()
+    }
+
+    fn transfer_stop_name(&self) -> Option<&str> {
+        if 2 <= self.parts.len() {
+            Some(self.departure().to().name())
+        } else {
+            None
+        }
+    }
+
+    fn is_on_demand(&self) -> bool {
+        Connection::is_on_demand(self)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        Connection::is_cancelled(self)
+    }
+
+    fn legs(&self) -> Vec<Leg<'_>> {
+        self.parts
+            .iter()
+            .map(|part| Leg {
+                departure_time: part.actual_departure_time(),
+                departure_delay: part.departure_delay(),
+                departure_stop_name: part.from().name(),
+                arrival_time: part.actual_arrival_time(),
+                arrival_delay: part.arrival_delay(),
+                arrival_stop_name: part.to().name(),
+                transport_type: part.line_transport_type(),
+                line_label: part.line_label(),
+                intermediate_stop_names: part
+                    .intermediate_stops()
+                    .iter()
+                    .map(|stop| stop.name())
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// Which symbols to use for displaying connections.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Theme {
+    /// Emoji and other unicode symbols.
+    #[default]
+    Emoji,
+    /// Plain ASCII, for terminals without emoji.
+    Ascii,
+}
+
+impl Theme {
+    /// The icon for `transport_type`.
+    pub fn icon(self, transport_type: TransportType) -> &'static str {
+        match self {
+            Theme::Emoji => transport_type.icon(),
+            Theme::Ascii => match transport_type {
+                TransportType::Bahn => "[R]",
+                TransportType::SBahn => "[S]",
+                TransportType::UBahn => "[U]",
+                TransportType::Tram => "[T]",
+                TransportType::Bus | TransportType::RegionalBus => "[B]",
+                TransportType::Schiff => "[F]",
+                TransportType::Ruftaxi => "[X]",
+                TransportType::Pedestrian => "[W]",
+            },
+        }
+    }
+
+    /// The icon for a line of `transport_type` labelled `label`.
+    ///
+    /// Tell S-Bahn lines which the API reports as regular trains apart from
+    /// regional and long-distance trains by their label.
+    pub fn line_icon(self, transport_type: TransportType, label: &str) -> &'static str {
+        let is_s_bahn_label = label
+            .strip_prefix('S')
+            .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()));
+        if transport_type == TransportType::Bahn && is_s_bahn_label {
+            self.icon(TransportType::SBahn)
+        } else {
+            self.icon(transport_type)
+        }
+    }
+
+    fn pick(self, emoji: &'static str, ascii: &'static str) -> &'static str {
+        match self {
+            Theme::Emoji => emoji,
+            Theme::Ascii => ascii,
+        }
+    }
+
+    /// Marks the time left to leave home.
+    fn home(self) -> &'static str {
+        self.pick("🏡", "=>")
+    }
+
+    /// Marks the departure time.
+    fn departure(self) -> &'static str {
+        self.pick("⚐", "dep ")
+    }
+
+    /// Marks the arrival time.
+    fn arrival(self) -> &'static str {
+        self.pick("⚑", "arr ")
+    }
+
+    /// Marks the departure stop.
+    fn stop(self) -> &'static str {
+        self.pick("🚏", "@ ")
+    }
+
+    /// Marks walking.
+    fn walk(self) -> &'static str {
+        self.pick("🏃", "[W]")
+    }
+
+    /// Points from one stop to another.
+    fn arrow(self) -> &'static str {
+        self.pick("→", "->")
+    }
+
+    /// Marks being late.
+    fn late(self) -> &'static str {
+        self.pick("⏰", "!")
+    }
+
+    /// Marks something unexpected.
+    fn warning(self) -> &'static str {
+        self.pick("⚠", "! ")
+    }
+
+    /// Marks an onward connection.
+    fn onward(self) -> &'static str {
+        self.pick("↪", "+")
+    }
+
+    /// Marks additional information.
+    fn info(self) -> &'static str {
+        self.pick("ℹ", "i")
+    }
+
+    /// Marks cancelled connections.
+    fn cancelled(self) -> &'static str {
+        self.pick("❌", "X")
+    }
+}
+
+/// Options for displaying connections.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOptions {
+    /// Show minutes and seconds if the remaining time is short.
+    pub seconds: bool,
+    /// Show only the departure and the first line.
+    pub compact: bool,
+    /// Show every leg of a connection on a line of its own.
+    pub legs: bool,
+    /// Show the stops every ride passes from departure to arrival, if known.
+    pub stops: bool,
+    /// Where home is, to show the distance to the start of connections.
+    pub home: Option<Coordinates>,
+    /// Show departure and arrival as minutes from now, instead of clock times.
+    pub relative: bool,
+    /// Which symbols to use.
+    pub theme: Theme,
+    /// Shorten the name of the departure stop to fit lines into this many columns.
+    pub max_width: Option<usize>,
+    /// How to format clock times; see [`parse_time_format`].
+    ///
+    /// Use [`DEFAULT_TIME_FORMAT`] if absent.
+    pub time_format: Option<String>,
+}
+
+/// The default format for clock times.
+pub const DEFAULT_TIME_FORMAT: &str = "%H:%M";
+
+/// Parse a strftime-like `format` for clock times.
+///
+/// See [`chrono::format::strftime`] for supported specifiers.
+pub fn parse_time_format(format: &str) -> Result<String> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        Err(anyhow!("Invalid time format: {}", format))
+    } else {
+        Ok(format.to_string())
+    }
+}
+
+/// A banner to tell that real time information is unavailable.
+pub fn display_realtime_unavailable(options: &DisplayOptions) -> String {
+    format!(
+        "{} realtime data unavailable, showing schedule.",
+        options.theme.warning().trim_end()
+    )
+}
+
+/// How to mark a connection among the connections of all routes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionMarks {
+    /// Highlight the connection, e.g. because it departs at a preferred time.
+    pub highlight: bool,
+    /// Mark the departure stop, because it's not the start station of the route.
+    pub unexpected_start: bool,
+    /// Color the time left to walk to the start, to tell routes apart.
+    pub tint: Option<AnsiColor>,
+}
+
+struct ConnectionDisplay<'a, C: DisplayableConnection> {
+    connection: &'a C,
+    walk_to_start: Duration,
+    marks: ConnectionMarks,
+    options: &'a DisplayOptions,
+}
+
+impl From<RouteColor> for AnsiColor {
+    fn from(color: RouteColor) -> Self {
+        match color {
+            RouteColor::Black => AnsiColor::Black,
+            RouteColor::Red => AnsiColor::Red,
+            RouteColor::Green => AnsiColor::Green,
+            RouteColor::Yellow => AnsiColor::Yellow,
+            RouteColor::Blue => AnsiColor::Blue,
+            RouteColor::Magenta => AnsiColor::Magenta,
+            RouteColor::Cyan => AnsiColor::Cyan,
+            RouteColor::White => AnsiColor::White,
+        }
+    }
+}
+
+fn delay_style(base_style: Style, delay: Option<Duration>) -> Style {
+    let color = match delay {
+        None => None,
+        Some(d) if d.is_zero() => Some(AnsiColor::Green.into()),
+        Some(_) => Some(AnsiColor::Red.into()),
+    };
+    base_style.fg_color(color)
+}
+
+/// The time left until we need to start walking to catch `connection`.
+fn start_in<C: DisplayableConnection>(connection: &C, walk_to_start: Duration) -> Duration {
+    connection.departure_time().with_timezone(&Utc) - walk_to_start - Utc::now()
+}
+
+/// Format `time` as clock time, or as minutes from now if relative.
+fn format_time(time: DateTime<FixedOffset>, options: &DisplayOptions) -> String {
+    if options.relative {
+        let minutes = whole_minutes(time.with_timezone(&Utc) - Utc::now()) as i64;
+        format!("{:+}m", minutes)
+    } else {
+        format_clock_time(time, options)
+    }
+}
+
+/// Format `time` as local clock time.
+fn format_clock_time(time: DateTime<FixedOffset>, options: &DisplayOptions) -> String {
+    time.with_timezone(&Local)
+        .format(
+            options
+                .time_format
+                .as_deref()
+                .unwrap_or(DEFAULT_TIME_FORMAT),
+        )
+        .to_string()
+}
+
+/// Round `duration` up to whole minutes.
+fn whole_minutes(duration: Duration) -> f64 {
+    ((duration.num_seconds() as f64) / 60.0).ceil()
+}
+
+/// The time remaining until we need to leave.
+///
+/// Negative if we should have left already.
+struct RemainingTime {
+    remaining: Duration,
+    /// Show minutes and seconds if less than two minutes remain.
+    seconds: bool,
+    /// Abbreviate minutes.
+    short: bool,
+    theme: Theme,
+}
+
+impl RemainingTime {
+    /// Whether we should have left already.
+    fn is_late(&self) -> bool {
+        self.remaining < Duration::zero()
+    }
+}
+
+impl Display for RemainingTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.remaining.num_seconds();
+        if self.is_late() {
+            // Round the delay up, rather than rounding towards zero, so that
+            // being half a minute late never looks like being on time.
+            write!(
+                f,
+                "{} {}m LATE",
+                self.theme.late(),
+                whole_minutes(-self.remaining)
+            )
+        } else if self.seconds && (0..120).contains(&seconds) {
+            write!(f, "{}m{:02}s", seconds / 60, seconds % 60)
+        } else if self.short {
+            write!(f, "{}m", whole_minutes(self.remaining))
+        } else {
+            write!(f, "{: >2} min", whole_minutes(self.remaining))
+        }
+    }
+}
+
+/// The approximate number of terminal columns `s` takes.
+///
+/// Skip ANSI escape sequences, and count emoji and other wide characters as
+/// two columns, and joiners and variation selectors as none.
+fn display_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        width += match c as u32 {
+            0x1B => {
+                // Skip over the parameters to the final byte of the sequence
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7E').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                0
+            }
+            0x200B..=0x200D | 0xFE00..=0xFE0F => 0,
+            0x1100..=0x115F
+            | 0x23E9..=0x23F3
+            | 0x2E80..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFF00..=0xFF60
+            | 0x1F000..=0x1FAFF => 2,
+            _ => 1,
+        }
+    }
+    width
+}
+
+/// Shorten `name` by at least `overflow` columns, and mark it as shortened.
+fn ellipsize(name: &str, overflow: usize) -> String {
+    let keep = name.chars().count().saturating_sub(overflow + 1);
+    let mut shortened = name.chars().take(keep).collect::<String>();
+    shortened.push('…');
+    shortened
+}
+
+impl<'a, C: DisplayableConnection> Display for ConnectionDisplay<'a, C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let stop_name = self.connection.departure_stop_name();
+        match self.options.max_width {
+            Some(max_width) if !self.options.compact => {
+                let mut line = String::new();
+                self.write_with_stop_name(&mut line, stop_name)?;
+                let width = line.lines().next().map_or(0, display_width);
+                match width.checked_sub(max_width) {
+                    Some(overflow) if 0 < overflow => {
+                        self.write_with_stop_name(f, &ellipsize(stop_name, overflow))
+                    }
+                    _ => f.write_str(&line),
+                }
+            }
+            _ => self.write_with_stop_name(f, stop_name),
+        }
+    }
+}
+
+impl<'a, C: DisplayableConnection> ConnectionDisplay<'a, C> {
+    fn write_with_stop_name<W: std::fmt::Write>(
+        &self,
+        f: &mut W,
+        stop_name: &str,
+    ) -> std::fmt::Result {
+        let departure_time = format_time(self.connection.departure_time(), self.options);
+        let arrival = format_time(self.connection.arrival_time(), self.options);
+        let start_in = start_in(self.connection, self.walk_to_start);
+
+        let base_style = if self.marks.highlight {
+            Style::new().bold()
+        } else {
+            Style::new()
+        };
+        let departure_style = delay_style(base_style, self.connection.departure_delay());
+        let arrival_style = delay_style(base_style, self.connection.arrival_delay());
+
+        let compact = self.options.compact;
+        let theme = self.options.theme;
+        let remaining = RemainingTime {
+            remaining: start_in,
+            seconds: self.options.seconds,
+            short: compact,
+            theme,
+        };
+        let tint_style = base_style.fg_color(self.marks.tint.map(Into::into));
+        write!(f, "{}", tint_style.render())?;
+        write!(
+            f,
+            "{} {}{}{}",
+            theme.home(),
+            if remaining.is_late() { "" } else { "In " },
+            remaining,
+            if compact { "" } else { "," },
+        )?;
+        if self.marks.tint.is_some() {
+            write!(f, "{}{}", tint_style.render_reset(), base_style.render())?;
+        }
+        write!(
+            f,
+            " {}{}{}{}{}",
+            theme.departure(),
+            departure_style.render(),
+            departure_time,
+            departure_style.render_reset(),
+            base_style.render(),
+        )?;
+        if !compact {
+            write!(
+                f,
+                " {}{}{}{}{}, {}{}{}",
+                theme.arrival(),
+                arrival_style.render(),
+                arrival,
+                arrival_style.render_reset(),
+                base_style.render(),
+                theme.stop(),
+                if self.marks.unexpected_start {
+                    theme.warning()
+                } else {
+                    ""
+                },
+                stop_name,
+            )?;
+            let distance = self
+                .options
+                .home
+                .zip(self.connection.departure_stop_coordinates())
+                .map(|(home, stop)| home.distance_to(&stop));
+            if let Some(distance) = distance {
+                write!(f, " (~{} m)", (distance / 10.0).round() * 10.0)?;
+            }
+        }
+        let transport_type = self.connection.transport_type();
+        let transfer = if compact {
+            None
+        } else {
+            self.connection.transfer_stop_name()
+        };
+        let result = match transfer {
+            // There's only one part in the connection so if it's a footway
+            //  we'll just walk to the destination
+            None if transport_type == TransportType::Pedestrian => write!(f, " {}", theme.walk()),
+            None => write!(
+                f,
+                " {}{}",
+                theme.line_icon(transport_type, self.connection.line_label()),
+                self.connection.line_label()
+            ),
+            Some(transfer) if transport_type == TransportType::Pedestrian => {
+                write!(f, " {} {}{}", theme.arrow(), theme.walk(), transfer)
+            }
+            Some(transfer) => write!(
+                f,
+                " {} {} {}{}",
+                theme.arrow(),
+                transfer,
+                theme.line_icon(transport_type, self.connection.line_label()),
+                self.connection.line_label()
+            ),
+        };
+        result?;
+        if self.connection.is_on_demand() {
+            write!(f, " {} book ahead", theme.info())?;
+        }
+        if self.connection.is_cancelled() {
+            write!(f, " {} cancelled", theme.cancelled())?;
+        }
+        write!(f, "{}", base_style.render_reset())?;
+        if (self.options.legs || self.options.stops) && !compact {
+            for leg in self.connection.legs() {
+                if self.options.legs {
+                    write!(f, "\n{}", LegDisplay(&leg, self.options))?;
+                }
+                let has_stops = leg.transport_type != TransportType::Pedestrian
+                    && !leg.intermediate_stop_names.is_empty();
+                if self.options.stops && has_stops {
+                    write!(f, "\n{}", StopsDisplay(&leg, self.options))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Display a single leg, with delays of this leg only.
+struct LegDisplay<'a, 'b>(&'a Leg<'b>, &'a DisplayOptions);
+
+impl<'a, 'b> Display for LegDisplay<'a, 'b> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (leg, options) = (self.0, self.1);
+        let theme = options.theme;
+        let departure_style = delay_style(Style::new(), leg.departure_delay);
+        let arrival_style = delay_style(Style::new(), leg.arrival_delay);
+        write!(f, "   ")?;
+        match leg.transport_type {
+            TransportType::Pedestrian => write!(f, "{}", theme.walk())?,
+            transport_type => write!(
+                f,
+                "{}{}",
+                theme.line_icon(transport_type, leg.line_label),
+                leg.line_label
+            )?,
+        }
+        write!(
+            f,
+            " {}{}{}{} {} {} {}{}{}{} {}",
+            theme.departure(),
+            departure_style.render(),
+            format_clock_time(leg.departure_time, options),
+            departure_style.render_reset(),
+            leg.departure_stop_name,
+            theme.arrow(),
+            theme.arrival(),
+            arrival_style.render(),
+            format_clock_time(leg.arrival_time, options),
+            arrival_style.render_reset(),
+            leg.arrival_stop_name,
+        )
+    }
+}
+
+/// Display the stops a leg passes, from departure to arrival.
+struct StopsDisplay<'a, 'b>(&'a Leg<'b>, &'a DisplayOptions);
+
+impl<'a, 'b> Display for StopsDisplay<'a, 'b> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (leg, theme) = (self.0, self.1.theme);
+        write!(
+            f,
+            "   {}{} {}",
+            theme.line_icon(leg.transport_type, leg.line_label),
+            leg.line_label,
+            leg.departure_stop_name
+        )?;
+        for name in leg
+            .intermediate_stop_names
+            .iter()
+            .chain(std::iter::once(&leg.arrival_stop_name))
+        {
+            write!(f, " {} {}", theme.arrow(), name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Display `connection` with the time left to walk to its start, marked with `marks`.
+pub fn display_with_walk_time<'a, C: DisplayableConnection>(
+    connection: &'a C,
+    walk_to_start: Duration,
+    marks: ConnectionMarks,
+    options: &'a DisplayOptions,
+) -> impl Display + 'a {
+    ConnectionDisplay {
+        connection,
+        walk_to_start,
+        marks,
+        options,
+    }
+}
+
+struct OnwardDisplay<'a, C: DisplayableConnection> {
+    connection: &'a C,
+    options: &'a DisplayOptions,
+}
+
+impl<'a, C: DisplayableConnection> Display for OnwardDisplay<'a, C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let theme = self.options.theme;
+        let departure_style = delay_style(Style::new(), self.connection.departure_delay());
+        let arrival_style = delay_style(Style::new(), self.connection.arrival_delay());
+        write!(
+            f,
+            "   {} onward {}{}{}{} {} ",
+            theme.onward(),
+            theme.departure(),
+            departure_style.render(),
+            format_time(self.connection.departure_time(), self.options),
+            departure_style.render_reset(),
+            self.connection.departure_stop_name(),
+        )?;
+        match self.connection.transport_type() {
+            TransportType::Pedestrian => write!(f, "{}", theme.walk())?,
+            transport_type => write!(
+                f,
+                "{}{}",
+                theme.line_icon(transport_type, self.connection.line_label()),
+                self.connection.line_label()
+            )?,
+        }
+        write!(
+            f,
+            " {}{}{}{}",
+            theme.arrival(),
+            arrival_style.render(),
+            format_time(self.connection.arrival_time(), self.options),
+            arrival_style.render_reset(),
+        )
+    }
+}
+
+/// Display `connection` as onward connection after another connection.
+pub fn display_onward<'a, C: DisplayableConnection>(
+    connection: &'a C,
+    options: &'a DisplayOptions,
+) -> impl Display + 'a {
+    OnwardDisplay {
+        connection,
+        options,
+    }
+}
+
+struct SummaryDisplay<'a, C: DisplayableConnection> {
+    connections: &'a [(Duration, &'a C)],
+    options: &'a DisplayOptions,
+}
+
+impl<'a, C: DisplayableConnection> Display for SummaryDisplay<'a, C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, (walk_to_start, connection)) in self.connections.iter().enumerate() {
+            if 0 < i {
+                write!(f, " | ")?;
+            }
+            let theme = self.options.theme;
+            match connection.transport_type() {
+                TransportType::Pedestrian => write!(f, "{}", theme.walk())?,
+                transport_type => write!(
+                    f,
+                    "{}{}",
+                    theme.line_icon(transport_type, connection.line_label()),
+                    connection.line_label()
+                )?,
+            }
+            let remaining = RemainingTime {
+                remaining: start_in(*connection, *walk_to_start),
+                seconds: self.options.seconds,
+                short: true,
+                theme,
+            };
+            if remaining.is_late() {
+                write!(f, " {}", remaining)?;
+            } else {
+                write!(f, " in {}", remaining)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Summarize `connections` in a single line.
+pub fn display_summary<'a, C: DisplayableConnection>(
+    connections: &'a [(Duration, &'a C)],
+    options: &'a DisplayOptions,
+) -> impl Display + 'a {
+    SummaryDisplay {
+        connections,
+        options,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::display::*;
+    use crate::mvg::ConnectionPart;
+    use chrono::{Timelike, Utc};
+    use pretty_assertions::assert_eq;
+
+    struct TestConnection {
+        departure: DateTime<FixedOffset>,
+        transfer: Option<&'static str>,
+    }
+
+    impl DisplayableConnection for TestConnection {
+        fn departure_time(&self) -> DateTime<FixedOffset> {
+            self.departure
+        }
+
+        fn departure_delay(&self) -> Option<Duration> {
+            None
+        }
+
+        fn arrival_time(&self) -> DateTime<FixedOffset> {
+            self.departure + Duration::minutes(20)
+        }
+
+        fn arrival_delay(&self) -> Option<Duration> {
+            None
+        }
+
+        fn departure_stop_name(&self) -> &str {
+            "Hauptbahnhof"
+        }
+
+        fn departure_stop_coordinates(&self) -> Option<Coordinates> {
+            Some(Coordinates {
+                latitude: 48.14003,
+                longitude: 11.56107,
+            })
+        }
+
+        fn transport_type(&self) -> TransportType {
+            TransportType::UBahn
+        }
+
+        fn line_label(&self) -> &str {
+            "U4"
+        }
+
+        fn transfer_stop_name(&self) -> Option<&str> {
+            self.transfer
+        }
+
+        fn is_on_demand(&self) -> bool {
+            false
+        }
+
+        fn is_cancelled(&self) -> bool {
+            false
+        }
+
+        fn legs(&self) -> Vec<Leg<'_>> {
+            Vec::new()
+        }
+    }
+
+    fn test_connection(transfer: Option<&'static str>) -> TestConnection {
+        // Round to whole seconds to stay clear of minute boundaries.
+        let departure = (Utc::now() + Duration::minutes(15))
+            .with_nanosecond(0)
+            .unwrap()
+            .fixed_offset();
+        TestConnection {
+            departure,
+            transfer,
+        }
+    }
+
+    #[test]
+    fn display_single_part_connection() {
+        let connection = test_connection(None);
+        let departure = connection.departure.with_timezone(&Local);
+        let arrival = connection.arrival_time().with_timezone(&Local);
+        assert_eq!(
+            display_with_walk_time(
+                &connection,
+                Duration::minutes(5),
+                ConnectionMarks::default(),
+                &DisplayOptions::default()
+            )
+            .to_string(),
+            format!(
+                "🏡 In 10 min, ⚐{} ⚑{}, 🚏Hauptbahnhof 🚇U4",
+                departure.format("%H:%M"),
+                arrival.format("%H:%M")
+            )
+        );
+    }
+
+    #[test]
+    fn display_connection_with_transfer() {
+        let connection = test_connection(Some("Odeonsplatz"));
+        let departure = connection.departure.with_timezone(&Local);
+        let arrival = connection.arrival_time().with_timezone(&Local);
+        assert_eq!(
+            display_with_walk_time(
+                &connection,
+                Duration::minutes(5),
+                ConnectionMarks::default(),
+                &DisplayOptions::default()
+            )
+            .to_string(),
+            format!(
+                "🏡 In 10 min, ⚐{} ⚑{}, 🚏Hauptbahnhof → Odeonsplatz 🚇U4",
+                departure.format("%H:%M"),
+                arrival.format("%H:%M")
+            )
+        );
+    }
+
+    #[test]
+    fn display_summary_of_connections() {
+        let first = test_connection(None);
+        let mut second = test_connection(None);
+        second.departure += Duration::minutes(7);
+        let connections = [
+            (Duration::minutes(5), &first),
+            (Duration::minutes(10), &second),
+        ];
+        assert_eq!(
+            display_summary(&connections, &DisplayOptions::default()).to_string(),
+            "🚇U4 in 10m | 🚇U4 in 12m"
+        );
+    }
+
+    #[test]
+    fn display_remaining_time() {
+        let remaining = |seconds, show_seconds| {
+            RemainingTime {
+                remaining: Duration::seconds(seconds),
+                seconds: show_seconds,
+                short: false,
+                theme: Theme::Emoji,
+            }
+            .to_string()
+        };
+        assert_eq!(remaining(90, false), " 2 min");
+        assert_eq!(remaining(90, true), "1m30s");
+        assert_eq!(remaining(5, true), "0m05s");
+        assert_eq!(remaining(120, true), " 2 min");
+        assert_eq!(remaining(600, true), "10 min");
+    }
+
+    #[test]
+    fn display_late_remaining_time() {
+        let remaining = |seconds| {
+            RemainingTime {
+                remaining: Duration::seconds(seconds),
+                seconds: true,
+                short: false,
+                theme: Theme::Emoji,
+            }
+            .to_string()
+        };
+        assert_eq!(remaining(0), "0m00s");
+        assert_eq!(remaining(-1), "⏰ 1m LATE");
+        assert_eq!(remaining(-90), "⏰ 2m LATE");
+        assert_eq!(remaining(-120), "⏰ 2m LATE");
+    }
+
+    #[test]
+    fn display_late_connection() {
+        let connection = test_connection(None);
+        let departure = connection.departure.with_timezone(&Local);
+        let options = DisplayOptions {
+            compact: true,
+            ..DisplayOptions::default()
+        };
+        assert_eq!(
+            display_with_walk_time(
+                &connection,
+                Duration::minutes(17),
+                ConnectionMarks::default(),
+                &options
+            )
+            .to_string(),
+            format!("🏡 ⏰ 2m LATE ⚐{} 🚇U4", departure.format("%H:%M"))
+        );
+    }
+
+    #[test]
+    fn display_cancelled_trip_from_fixture() {
+        let connections = Connection::from_fixture("cancelled_trip");
+        assert!(connections[0].is_cancelled());
+        let render = |theme| {
+            let options = DisplayOptions {
+                theme,
+                ..DisplayOptions::default()
+            };
+            let rendered = display_with_walk_time(
+                &connections[0],
+                Duration::minutes(5),
+                ConnectionMarks::default(),
+                &options,
+            )
+            .to_string();
+            rendered
+        };
+        assert!(
+            render(Theme::Emoji).contains(" ❌ cancelled"),
+            "{}",
+            render(Theme::Emoji)
+        );
+        assert!(
+            render(Theme::Ascii).contains(" X cancelled"),
+            "{}",
+            render(Theme::Ascii)
+        );
+        let connections = Connection::from_fixture("connections_with_delays");
+        let rendered = display_with_walk_time(
+            &connections[0],
+            Duration::minutes(5),
+            ConnectionMarks::default(),
+            &DisplayOptions::default(),
+        )
+        .to_string();
+        assert!(!rendered.contains("cancelled"), "{}", rendered);
+    }
+
+    #[test]
+    fn display_connections_from_fixture() {
+        let connections = Connection::from_fixture("connections_with_delays");
+        let rendered = connections
+            .iter()
+            .map(|connection| {
+                let departure = connection.departure_time().with_timezone(&Local);
+                let arrival = connection.arrival_time().with_timezone(&Local);
+                let line = display_with_walk_time(
+                    connection,
+                    Duration::minutes(5),
+                    ConnectionMarks::default(),
+                    &DisplayOptions::default(),
+                )
+                .to_string();
+                // Strip the remaining time which depends on the current time.
+                let (_, line) = line.split_once(',').unwrap();
+                (line.to_string(), departure, arrival)
+            })
+            .collect::<Vec<_>>();
+        let green = Style::new().fg_color(Some(AnsiColor::Green.into()));
+        let red = Style::new().fg_color(Some(AnsiColor::Red.into()));
+        let plain = Style::new();
+        assert_eq!(
+            rendered[0].0,
+            format!(
+                " ⚐{}{}{}{} ⚑{}{}{}{}, 🚏Hauptbahnhof → Max-Weber-Platz 🚇U4{}",
+                green.render(),
+                rendered[0].1.format("%H:%M"),
+                green.render_reset(),
+                plain.render(),
+                red.render(),
+                rendered[0].2.format("%H:%M"),
+                red.render_reset(),
+                plain.render(),
+                plain.render_reset(),
+            )
+        );
+        assert_eq!(
+            rendered[1].0,
+            format!(
+                " ⚐{}{}{}{} ⚑{}{}{}{}, 🚏Hauptbahnhof {}S3{}",
+                red.render(),
+                rendered[1].1.format("%H:%M"),
+                red.render_reset(),
+                plain.render(),
+                red.render(),
+                rendered[1].2.format("%H:%M"),
+                red.render_reset(),
+                plain.render(),
+                TransportType::SBahn.icon(),
+                plain.render_reset(),
+            )
+        );
+    }
+
+    #[test]
+    fn tint_time_left_to_walk() {
+        let connections = Connection::from_fixture("connections_with_delays");
+        let options = DisplayOptions::default();
+        let rendered = display_with_walk_time(
+            &connections[0],
+            Duration::minutes(5),
+            ConnectionMarks {
+                tint: Some(AnsiColor::Magenta),
+                ..ConnectionMarks::default()
+            },
+            &options,
+        )
+        .to_string();
+        let magenta = Style::new().fg_color(Some(AnsiColor::Magenta.into()));
+        assert!(rendered.starts_with(&magenta.render().to_string()));
+        let prefix_end = rendered.find(',').unwrap();
+        assert!(rendered[prefix_end..].starts_with(&format!(",{}", magenta.render_reset())));
+    }
+
+    #[test]
+    fn display_intermediate_stops_of_rides() {
+        let departure = DateTime::parse_from_rfc3339("2024-06-01T17:42:00+02:00").unwrap();
+        let connection = Connection::from_parts(vec![
+            ConnectionPart::new(
+                TransportType::Pedestrian,
+                "",
+                departure - Duration::minutes(3),
+                departure,
+            )
+            .between("Sonnenstraße", "Hauptbahnhof")
+            .via(&["Bayerstraße"]),
+            ConnectionPart::new(
+                TransportType::UBahn,
+                "U4",
+                departure,
+                departure + Duration::minutes(7),
+            )
+            .between("Hauptbahnhof", "Max-Weber-Platz")
+            .via(&["Karlsplatz (Stachus)", "Odeonsplatz", "Lehel"]),
+            ConnectionPart::new(
+                TransportType::SBahn,
+                "S3",
+                departure + Duration::minutes(10),
+                departure + Duration::minutes(14),
+            )
+            .between("Max-Weber-Platz", "Ostbahnhof"),
+        ]);
+        let options = DisplayOptions {
+            stops: true,
+            ..DisplayOptions::default()
+        };
+        let rendered = display_with_walk_time(
+            &connection,
+            Duration::minutes(5),
+            ConnectionMarks::default(),
+            &options,
+        )
+        .to_string();
+        let stops = rendered.lines().skip(1).collect::<Vec<_>>();
+        assert_eq!(
+            stops,
+            vec!["   🚇U4 Hauptbahnhof → Karlsplatz (Stachus) → Odeonsplatz → Lehel → Max-Weber-Platz"]
+        );
+    }
+
+    #[test]
+    fn display_legs_with_their_own_delays() {
+        let connections = Connection::from_fixture("connections_with_delays");
+        let options = DisplayOptions {
+            legs: true,
+            ..DisplayOptions::default()
+        };
+        let rendered = display_with_walk_time(
+            &connections[0],
+            Duration::minutes(5),
+            ConnectionMarks::default(),
+            &options,
+        )
+        .to_string();
+        let legs = rendered.lines().skip(1).collect::<Vec<_>>();
+        let green = Style::new().fg_color(Some(AnsiColor::Green.into()));
+        let red = Style::new().fg_color(Some(AnsiColor::Red.into()));
+        let local = |s| {
+            DateTime::parse_from_rfc3339(s)
+                .unwrap()
+                .with_timezone(&Local)
+                .format("%H:%M")
+        };
+        assert_eq!(
+            legs,
+            vec![
+                format!(
+                    "   🚇U4 ⚐{}{}{} Hauptbahnhof → ⚑{}{}{} Max-Weber-Platz",
+                    green.render(),
+                    local("2024-06-01T17:42:00+02:00"),
+                    green.render_reset(),
+                    green.render(),
+                    local("2024-06-01T17:49:00+02:00"),
+                    green.render_reset(),
+                ),
+                format!(
+                    "   {}19 ⚐{}{}{} Max-Weber-Platz → ⚑{}{}{} Ostbahnhof",
+                    TransportType::Tram.icon(),
+                    red.render(),
+                    local("2024-06-01T17:56:00+02:00"),
+                    red.render_reset(),
+                    red.render(),
+                    local("2024-06-01T18:01:00+02:00"),
+                    red.render_reset(),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn display_distance_from_home() {
+        let connection = test_connection(None);
+        let departure = connection.departure.with_timezone(&Local);
+        let arrival = connection.arrival_time().with_timezone(&Local);
+        let options = DisplayOptions {
+            home: Some(Coordinates {
+                latitude: 48.14,
+                longitude: 11.565,
+            }),
+            ..DisplayOptions::default()
+        };
+        assert_eq!(
+            display_with_walk_time(
+                &connection,
+                Duration::minutes(5),
+                ConnectionMarks::default(),
+                &options
+            )
+            .to_string(),
+            format!(
+                "🏡 In 10 min, ⚐{} ⚑{}, 🚏Hauptbahnhof (~290 m) 🚇U4",
+                departure.format("%H:%M"),
+                arrival.format("%H:%M")
+            )
+        );
+    }
+
+    #[test]
+    fn display_unexpected_start() {
+        let connection = test_connection(None);
+        let departure = connection.departure.with_timezone(&Local);
+        let arrival = connection.arrival_time().with_timezone(&Local);
+        assert_eq!(
+            display_with_walk_time(
+                &connection,
+                Duration::minutes(5),
+                ConnectionMarks {
+                    unexpected_start: true,
+                    ..ConnectionMarks::default()
+                },
+                &DisplayOptions::default()
+            )
+            .to_string(),
+            format!(
+                "🏡 In 10 min, ⚐{} ⚑{}, 🚏⚠Hauptbahnhof 🚇U4",
+                departure.format("%H:%M"),
+                arrival.format("%H:%M")
+            )
+        );
+    }
+
+    #[test]
+    fn display_relative_times() {
+        let connection = test_connection(None);
+        let options = DisplayOptions {
+            relative: true,
+            ..DisplayOptions::default()
+        };
+        assert_eq!(
+            display_with_walk_time(
+                &connection,
+                Duration::minutes(5),
+                ConnectionMarks::default(),
+                &options
+            )
+            .to_string(),
+            "🏡 In 10 min, ⚐+15m ⚑+35m, 🚏Hauptbahnhof 🚇U4"
+        );
+    }
+
+    #[test]
+    fn display_ascii_theme() {
+        let connection = test_connection(Some("Odeonsplatz"));
+        let departure = connection.departure.with_timezone(&Local);
+        let arrival = connection.arrival_time().with_timezone(&Local);
+        let options = DisplayOptions {
+            theme: Theme::Ascii,
+            ..DisplayOptions::default()
+        };
+        let line = display_with_walk_time(
+            &connection,
+            Duration::minutes(5),
+            ConnectionMarks {
+                unexpected_start: true,
+                ..ConnectionMarks::default()
+            },
+            &options,
+        )
+        .to_string();
+        assert_eq!(
+            line,
+            format!(
+                "=> In 10 min, dep {} arr {}, @ ! Hauptbahnhof -> Odeonsplatz [U]U4",
+                departure.format("%H:%M"),
+                arrival.format("%H:%M")
+            )
+        );
+        assert!(line.is_ascii());
+        let connections = [(Duration::minutes(20), &connection)];
+        assert_eq!(
+            display_summary(&connections, &options).to_string(),
+            "[U]U4 ! 5m LATE"
+        );
+    }
+
+    #[test]
+    fn display_onward_connection() {
+        let connection = test_connection(None);
+        let departure = connection.departure.with_timezone(&Local);
+        let arrival = connection.arrival_time().with_timezone(&Local);
+        assert_eq!(
+            display_onward(&connection, &DisplayOptions::default()).to_string(),
+            format!(
+                "   ↪ onward ⚐{} Hauptbahnhof 🚇U4 ⚑{}",
+                departure.format("%H:%M"),
+                arrival.format("%H:%M")
+            )
+        );
+    }
+
+    #[test]
+    fn line_icons_tell_trains_apart() {
+        let theme = Theme::Emoji;
+        assert_eq!(theme.line_icon(TransportType::SBahn, "S3"), "🚆");
+        assert_eq!(theme.line_icon(TransportType::Bahn, "S2"), "🚆");
+        assert_eq!(theme.line_icon(TransportType::Bahn, "RB16"), "🚄");
+        assert_eq!(theme.line_icon(TransportType::Bahn, "Sbahn"), "🚄");
+        assert_eq!(Theme::Ascii.line_icon(TransportType::Bahn, "S2"), "[S]");
+        assert_eq!(Theme::Ascii.line_icon(TransportType::Bahn, "RE1"), "[R]");
+    }
+
+    #[test]
+    fn display_compact_connection() {
+        let connection = test_connection(Some("Odeonsplatz"));
+        let departure = connection.departure.with_timezone(&Local);
+        let options = DisplayOptions {
+            compact: true,
+            ..DisplayOptions::default()
+        };
+        assert_eq!(
+            display_with_walk_time(
+                &connection,
+                Duration::minutes(5),
+                ConnectionMarks::default(),
+                &options
+            )
+            .to_string(),
+            format!("🏡 In 10m ⚐{} 🚇U4", departure.format("%H:%M"))
+        );
+    }
+
+    #[test]
+    fn display_realtime_unavailable_banner() {
+        assert_eq!(
+            display_realtime_unavailable(&DisplayOptions::default()),
+            "⚠ realtime data unavailable, showing schedule."
+        );
+        let options = DisplayOptions {
+            theme: Theme::Ascii,
+            ..DisplayOptions::default()
+        };
+        assert_eq!(
+            display_realtime_unavailable(&options),
+            "! realtime data unavailable, showing schedule."
+        );
+    }
+
+    #[test]
+    fn parse_time_formats() {
+        assert_eq!(parse_time_format("%H:%M").unwrap(), "%H:%M");
+        assert_eq!(parse_time_format("%I:%M %p").unwrap(), "%I:%M %p");
+        assert_eq!(
+            parse_time_format("%H:%Q").unwrap_err().to_string(),
+            "Invalid time format: %H:%Q"
+        );
+    }
+
+    #[test]
+    fn display_connection_with_time_format() {
+        let connection = test_connection(None);
+        let departure = connection.departure.with_timezone(&Local);
+        let arrival = connection.arrival_time().with_timezone(&Local);
+        let options = DisplayOptions {
+            time_format: Some("%H:%M:%S".to_string()),
+            ..DisplayOptions::default()
+        };
+        assert_eq!(
+            display_with_walk_time(
+                &connection,
+                Duration::minutes(5),
+                ConnectionMarks::default(),
+                &options
+            )
+            .to_string(),
+            format!(
+                "🏡 In 10 min, ⚐{} ⚑{}, 🚏Hauptbahnhof 🚇U4",
+                departure.format("%H:%M:%S"),
+                arrival.format("%H:%M:%S")
+            )
+        );
+    }
+
+    #[test]
+    fn display_width_of_symbols() {
+        assert_eq!(display_width("Hauptbahnhof"), 12);
+        assert_eq!(display_width("🏡 In"), 5);
+        assert_eq!(display_width("⚐17:42"), 6);
+        let style = Style::new().bold();
+        assert_eq!(
+            display_width(&format!("{}17:42{}", style.render(), style.render_reset())),
+            5
+        );
+    }
+
+    #[test]
+    fn truncate_stop_name_to_width() {
+        let connection = test_connection(None);
+        let options = DisplayOptions {
+            max_width: Some(40),
+            ..DisplayOptions::default()
+        };
+        let line = display_with_walk_time(
+            &connection,
+            Duration::minutes(5),
+            ConnectionMarks::default(),
+            &options,
+        )
+        .to_string();
+        assert_eq!(display_width(&line), 40);
+        assert!(line.contains("🚏Hau… 🚇U4"), "Unexpected line: {line}");
+
+        let options = DisplayOptions {
+            max_width: Some(80),
+            ..DisplayOptions::default()
+        };
+        let line = display_with_walk_time(
+            &connection,
+            Duration::minutes(5),
+            ConnectionMarks::default(),
+            &options,
+        )
+        .to_string();
+        assert!(
+            line.contains("🚏Hauptbahnhof 🚇U4"),
+            "Unexpected line: {line}"
+        );
+    }
+}
@@ -8,13 +8,17 @@
 
 //! MVG connections for the way home.
 
-use std::fmt::{Display, Formatter};
+use std::collections::{BTreeSet, HashMap};
+use std::io::IsTerminal;
 use std::path::PathBuf;
+use std::process::ExitCode;
 
-use anstyle::{AnsiColor, Style};
-use anyhow::Result;
-use chrono::{DateTime, Duration, Local, Utc};
-use clap::Parser;
+use anyhow::{anyhow, Context, Result};
+use chrono::{
+    DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use futures::future::{join_all, try_join, try_join_all};
 use tracing::{debug, event, warn, Level};
 
 use tracing_futures::Instrument;
@@ -22,159 +26,1069 @@ use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 mod cache;
 mod config;
+mod display;
 mod mvg;
 
 use cache::*;
 use config::*;
+use display::*;
 use mvg::*;
 
-struct ConnectionDisplay<'a> {
-    connection: &'a Connection,
-    walk_to_start: Duration,
-}
-
-impl<'a> Display for ConnectionDisplay<'a> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let departure_time = self
-            .connection
-            .actual_departure_time()
-            .with_timezone(&Local);
-        let arrival = self.connection.actual_arrival_time().with_timezone(&Local);
-        let start_in = departure_time - self.walk_to_start - Local::now();
-
-        let departure_stop = self.connection.departure();
-        let departure_color = match self.connection.departure_delay() {
-            None => None,
-            Some(d) if d.is_zero() => Some(AnsiColor::Green.into()),
-            Some(_) => Some(AnsiColor::Red.into()),
-        };
-        let departure_style = Style::new().fg_color(departure_color);
-        let arrival_color = match self.connection.arrival_delay() {
-            None => None,
-            Some(d) if d.is_zero() => Some(AnsiColor::Green.into()),
-            Some(_) => Some(AnsiColor::Red.into()),
-        };
-        let arrival_style = Style::new().fg_color(arrival_color);
-
-        write!(
-            f,
-            "🏡 In {: >2} min, ⚐{}{}{} ⚑{}{}{}, 🚏{}",
-            ((start_in.num_seconds() as f64) / 60.0).ceil(),
-            departure_style.render(),
-            departure_time.format("%H:%M"),
-            departure_style.render_reset(),
-            arrival_style.render(),
-            arrival.format("%H:%M"),
-            arrival_style.render_reset(),
-            self.connection.departure().from().name(),
-        )?;
-        if self.connection.parts.len() == 1 {
-            match departure_stop.line_transport_type() {
-                // There's only one part in the connection so if it's a footway
-                //  we'll just walk to the destination
-                TransportType::Pedestrian => write!(f, " 🏃"),
-                _ => {
-                    write!(
-                        f,
-                        " {}{}",
-                        departure_stop.line_transport_type().icon(),
-                        departure_stop.line_label()
-                    )
-                }
-            }
-        } else if 2 <= self.connection.parts.len() {
-            match departure_stop.line_transport_type() {
-                TransportType::Pedestrian => write!(f, " → 🏃{}", departure_stop.to().name()),
-                _ => {
-                    write!(
-                        f,
-                        " → {} {}{}",
-                        departure_stop.to().name(),
-                        departure_stop.line_transport_type().icon(),
-                        departure_stop.line_label()
-                    )
-                }
-            }
-        } else {
-            Ok(())
-        }
-    }
-}
-
-fn display_with_walk_time(
-    connection: &'_ Connection,
-    walk_to_start: Duration,
-) -> impl Display + '_ {
-    ConnectionDisplay {
-        connection,
-        walk_to_start,
-    }
-}
-
 #[derive(Debug, Clone, Parser)]
-#[command(author, version, about)]
+#[command(
+    author,
+    version,
+    about,
+    disable_version_flag = true,
+    after_help = "Exit status is 0 if there are connections, 4 if no connection is reachable, 5 if a route has no connections with --fail-on-empty, and 1 on error."
+)]
 struct Arguments {
-    /// Use a different configuration file
+    /// Print version; with --verbose, also print build information.
+    #[arg(short = 'V', long)]
+    version: bool,
+    /// Print more information, with --version.
+    #[arg(long, requires = "version")]
+    verbose: bool,
+    /// Use a different configuration file; repeat to merge multiple files
     #[arg(long, value_name = "FILE")]
-    config: Option<PathBuf>,
+    config: Vec<PathBuf>,
     /// Number of connections to show
-    #[arg(short = 'n', long, default_value_t = 10, value_name = "N")]
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 10,
+        value_name = "N",
+        env = "MVG_HOME_CONNECTIONS"
+    )]
     connections: u16,
     /// Get fresh connections
     #[arg(long)]
     fresh: bool,
+    /// Refresh connections of all routes, but keep the cache otherwise
+    #[arg(long)]
+    refresh_all: bool,
+    /// Refresh connections of routes matching PATTERN, but keep other routes cached.
+    ///
+    /// PATTERN is either START→DESTINATION or START->DESTINATION, or a single
+    /// name which matches either start or destination.  Names match
+    /// case-insensitive substrings.
+    #[arg(long, value_name = "PATTERN")]
+    route: Option<String>,
+    /// Only show connections which the last run with this flag did not show.
+    #[arg(long)]
+    since_last: bool,
+    /// Print the unprocessed JSON response of the MVG API for every route and exit.
+    ///
+    /// Bypass the cache entirely.
+    #[arg(long)]
+    raw: bool,
+    /// Use the cache, but do not save it.
+    #[arg(long)]
+    no_save: bool,
+    /// Do not contact the MVG API, and only show cached connections.
+    ///
+    /// Show connections from the snapshot of the last shown connections for
+    /// routes without cached connections.
+    #[arg(long, conflicts_with_all = ["raw", "refresh_all", "track", "start_times", "day"])]
+    offline: bool,
     /// Show contents of the cache and exit.
     #[arg(long)]
     dump_cache: bool,
+    /// Show contents of the cache, tagged with the eviction stage which would evict each connection, and exit.
+    ///
+    /// Do not refresh connections from the MVG API, and do not save the cache.
+    #[arg(long, conflicts_with_all = ["dump_cache", "raw"])]
+    explain_eviction: bool,
+    /// Connect to the MVG API directly, without looking up a proxy.
+    #[arg(long, env = "MVG_NO_PROXY", value_parser = clap::builder::FalseyValueParser::new())]
+    direct: bool,
+    /// The proxy from the configuration, to use instead of looking up a proxy.
+    #[arg(skip)]
+    proxy: Option<reqwest::Url>,
+    /// Trust the root certificate in the given PEM file for MVG API requests.
+    #[arg(long, value_name = "FILE", env = "MVG_CA_CERT")]
+    ca_cert: Option<PathBuf>,
+    /// Show seconds if there's little time left to leave.
+    #[arg(long)]
+    seconds: bool,
+    /// Only show the connection departing at the given time, with fresh real time information.
+    ///
+    /// Combine with --route to only track the connection of matching routes.
+    #[arg(long, value_name = "HH:MM")]
+    track: Option<NaiveTime>,
+    /// Only show departure time and line of connections.
+    #[arg(long, conflicts_with = "legs")]
+    compact: bool,
+    /// Show departure and arrival as minutes from now, instead of clock times.
+    #[arg(long)]
+    relative: bool,
+    /// Format departure and arrival times with the given strftime-like format.
+    #[arg(long, value_name = "FORMAT", default_value = DEFAULT_TIME_FORMAT, value_parser = parse_time_format)]
+    time_format: String,
+    /// Use plain ASCII instead of emoji and other symbols.
+    #[arg(long)]
+    no_emoji: bool,
+    /// Do not shorten departure stop names to fit lines into the terminal.
+    #[arg(long)]
+    no_truncate: bool,
+    /// Show every leg of connections with its own departure, arrival and delays.
+    #[arg(long)]
+    legs: bool,
+    /// Show the stops every ride of connections passes, if the MVG API tells them.
+    #[arg(long, conflicts_with = "compact")]
+    show_stops: bool,
+    /// Only show connections which depart or arrive late.
+    #[arg(long)]
+    only_delayed: bool,
+    /// Exit with status 5 if any route has no connections left, e.g. for monitoring.
+    #[arg(long, conflicts_with_all = ["count", "dump_cache", "explain_eviction", "start_times", "day"])]
+    fail_on_empty: bool,
+    /// Print the number of reachable connections and exit.
+    #[arg(long)]
+    count: bool,
+    /// Print the path of the configuration file(s) to use and exit.
+    #[arg(long)]
+    print_config_path: bool,
+    /// Write the configuration to use to FILE and exit.
+    ///
+    /// Write the merged configuration of all configuration files, or the
+    /// configuration of ad-hoc routes, e.g. to keep ad-hoc routes for later.
+    #[arg(long, value_name = "FILE")]
+    write_config: Option<PathBuf>,
+    /// Use the given cache file instead of the cache file for the configuration.
+    ///
+    /// Read and write JSON if FILE has a .json extension, e.g. to edit the cache by hand.
+    #[arg(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+    /// Print the paths of all cache files and exit.
+    ///
+    /// Every set of configuration files has a cache file of its own in
+    /// $XDG_CACHE_HOME/de.swsnr.home; the default configuration uses the
+    /// "connections" file, and other configuration files "connections-HASH".
+    #[arg(long)]
+    list_caches: bool,
+    /// Check whether the MVG API is reachable and exit.
+    #[arg(long)]
+    ping: bool,
     /// Start at the given time instead of now.
-    #[arg(short = 's', long, default_value_t = Local::now())]
-    start_time: DateTime<Local>,
+    #[arg(short = 's', long)]
+    start_time: Option<DateTime<Local>>,
+    /// Show connections for each of the given start times, grouped by start time.
+    ///
+    /// Neither use nor update the cache.
+    #[arg(
+        long,
+        value_name = "TIME",
+        value_delimiter = ',',
+        conflicts_with_all = ["start_time", "arrive_before", "horizon", "track", "raw", "count", "dump_cache"]
+    )]
+    start_times: Vec<DateTime<Local>>,
+    /// Plan connections for the given day, grouped by hour, e.g. 2024-06-01 or "2024-06-01 07:00".
+    ///
+    /// Start at midnight unless DAY has a time, and show all connections until
+    /// the end of the day.  Neither use nor update the cache, and show
+    /// connections regardless of the time to walk to the start.
+    #[arg(
+        long,
+        value_name = "DAY",
+        value_parser = parse_day,
+        conflicts_with_all = ["start_time", "start_times", "arrive_before", "horizon", "track", "raw", "count", "dump_cache", "since_last"]
+    )]
+    day: Option<NaiveDateTime>,
+    /// Only show the earliest connection of every route.
+    #[arg(long, conflicts_with = "max_per_route")]
+    earliest_only: bool,
+    /// Show at most M connections of every route.
+    #[arg(long, value_name = "M")]
+    max_per_route: Option<usize>,
+    /// Prefer connections with fewer transfers among connections which start
+    /// around the same time.
+    #[arg(long)]
+    prefer_direct: bool,
+    /// How to order connections.
+    #[arg(long, value_enum, default_value_t = SortOrder::Earliest, conflicts_with = "prefer_direct")]
+    sort: SortOrder,
+    /// Summarize the next connection of every route before all connections.
+    #[arg(long)]
+    summary: bool,
+    /// Only show connections which start with a train or tram.
+    #[arg(long, conflicts_with = "bus_only")]
+    rail_only: bool,
+    /// Only show connections which start with a bus.
+    #[arg(long)]
+    bus_only: bool,
+    /// Show connections which run on demand only and require booking ahead.
+    #[arg(long)]
+    include_on_demand: bool,
+    /// Only show connections which leave the full time to walk to the start.
+    #[arg(long, conflicts_with = "include_barely_reachable")]
+    only_reachable_now: bool,
+    /// Also show connections which leave only half the time to walk to the start.
+    #[arg(long)]
+    include_barely_reachable: bool,
+    /// Hide connections which start with the given transport type, e.g. u-bahn or regional-bus.
+    #[arg(long = "exclude-transport", value_name = "TYPE")]
+    exclude_transports: Vec<TransportType>,
+    /// Hide connections which do not arrive before the given time, as planned.
+    #[arg(long, value_name = "HH:MM")]
+    arrive_before: Option<NaiveTime>,
+    /// Hide connections which depart later than DURATION after the start time.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    horizon: Option<Duration>,
+    /// Hide connections which walk longer than DURATION between lines, in total.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    max_total_walk: Option<Duration>,
+    /// Hide connections which take longer than DURATION from departure to arrival, as planned.
+    #[arg(long, alias = "limit-time-to-destination", value_name = "DURATION", value_parser = parse_duration)]
+    max_trip: Option<Duration>,
+    /// Show connections from START to --destination, instead of configured routes.
+    ///
+    /// Neither read the configuration nor use the cache.
+    #[arg(
+        long,
+        value_name = "START",
+        requires = "destination",
+        conflicts_with = "config"
+    )]
+    start: Option<String>,
+    /// The destination for --start.
+    #[arg(long, value_name = "DESTINATION", requires = "start")]
+    destination: Option<String>,
+    /// How much time to account for to walk to --start.
+    #[arg(
+        long = "walk",
+        value_name = "DURATION",
+        requires = "start",
+        value_parser = parse_duration
+    )]
+    walk_to_start: Option<Duration>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// How to order connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SortOrder {
+    /// Show connections I have to leave for first at the top.
+    #[value(name = "earliest-leave")]
+    Earliest,
+    /// Show connections I can leave for latest at the top.
+    #[value(name = "latest-leave")]
+    Latest,
+    /// Show connections I have to leave for first at the top, accounting for delays.
+    #[value(name = "actual-leave")]
+    Actual,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Show connections from START to the given destinations, instead of configured routes.
+    ///
+    /// Neither read the configuration nor use the cache.
+    From {
+        /// The start station.
+        start: String,
+        /// A destination station; repeat to compare multiple destinations.
+        #[arg(long = "to", value_name = "DESTINATION", required = true)]
+        destinations: Vec<String>,
+        /// How much time to account for to walk to the start station.
+        #[arg(long = "walk", value_name = "DURATION", default_value = "0s", value_parser = parse_duration)]
+        walk_to_start: Duration,
+    },
+    /// Remove cache files which this version cannot read, and exit.
+    PruneCache,
+}
+
+impl Command {
+    /// The configuration for this command, if it shows connections.
+    fn config(&self) -> Option<Config> {
+        match self {
+            Command::From {
+                start,
+                destinations,
+                walk_to_start,
+            } => Some(ad_hoc_config(start, destinations, *walk_to_start)),
+            Command::PruneCache => None,
+        }
+    }
+}
+
+/// A configuration with routes from `start` to every destination in `destinations`.
+fn ad_hoc_config<S: AsRef<str>>(
+    start: &str,
+    destinations: &[S],
+    walk_to_start: Duration,
+) -> Config {
+    Config {
+        connections: destinations
+            .iter()
+            .map(|destination| DesiredConnection {
+                start: start.to_string(),
+                destination: destination.as_ref().to_string(),
+                walk_to_start,
+                ignore_starting_with: Vec::new(),
+                preferred_times: Vec::new(),
+                active_days: Vec::new(),
+                active_hours: None,
+                onward: None,
+                avoid_stops: Vec::new(),
+                color: None,
+            })
+            .collect(),
+        ..Config::default()
+    }
+}
+
+/// Parse a human readable duration, e.g. `5min`.
+fn parse_duration(s: &str) -> Result<Duration> {
+    let duration = humantime::parse_duration(s)?;
+    Ok(Duration::from_std(duration)?)
+}
+
+/// Parse a day with an optional time, e.g. `2024-06-01` or `2024-06-01 07:00`.
+fn parse_day(s: &str) -> Result<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d").map(|day| day.and_time(NaiveTime::MIN))
+        })
+        .with_context(|| format!("Invalid day {s}, expected YYYY-MM-DD or YYYY-MM-DD HH:MM"))
 }
 
 impl Arguments {
+    /// The configuration for ad-hoc routes from the command line, if any.
+    fn ad_hoc_config(&self) -> Result<Option<Config>> {
+        match (&self.command, &self.start, &self.destination) {
+            (Some(_), Some(_), _) => Err(anyhow!("--start cannot be used with a command")),
+            (Some(command), _, _) => Ok(command.config()),
+            (None, Some(start), Some(destination)) => Ok(Some(ad_hoc_config(
+                start,
+                &[destination],
+                self.walk_to_start.unwrap_or_else(Duration::zero),
+            ))),
+            _ => Ok(None),
+        }
+    }
+
+    fn client_builder(&self) -> Result<reqwest::ClientBuilder> {
+        let builder = reqwest::ClientBuilder::new();
+        match &self.ca_cert {
+            Some(file) => {
+                let pem = std::fs::read(file).with_context(|| {
+                    format!("Failed to read root certificate from {}", file.display())
+                })?;
+                let certificate = reqwest::Certificate::from_pem(&pem).with_context(|| {
+                    format!("Failed to parse root certificate from {}", file.display())
+                })?;
+                debug!("Adding root certificate from {}", file.display());
+                Ok(builder.add_root_certificate(certificate))
+            }
+            None => Ok(builder),
+        }
+    }
+
+    /// Use the proxy settings of `config`, unless the command line asks for a direct connection.
+    fn use_proxy_settings(&mut self, config: &Config) {
+        self.direct |= config.no_proxy;
+        self.proxy = config.proxy.clone();
+    }
+
+    async fn mvg(&self) -> Result<Mvg> {
+        let builder = self.client_builder()?;
+        if self.direct {
+            debug!("Skipping proxy lookup per command line arguments or configuration");
+            Mvg::direct_with_client_builder(builder)
+        } else if let Some(proxy) = &self.proxy {
+            debug!("Using proxy {proxy} per configuration");
+            Mvg::with_fixed_proxy(builder, proxy.clone())
+        } else {
+            Mvg::with_client_builder(builder)
+                .await?
+                .with_direct_fallback(self.client_builder()?)
+        }
+    }
+
+    /// Load the configuration from the given files, or from the default location.
+    fn load_config(&self) -> Result<Config> {
+        if self.config.is_empty() {
+            Config::from_default_location()
+        } else {
+            Config::from_files(&self.config)
+        }
+    }
+
+    /// The path of the cache file to use.
+    fn cache_path(&self) -> PathBuf {
+        self.cache
+            .clone()
+            .unwrap_or_else(|| cache_path(&self.config))
+    }
+
+    /// Load the snapshot of the last shown connections, or an empty cache if it fails to load.
+    fn load_last_good(&self) -> ConnectionsCache {
+        let path = last_good_path(&self.cache_path());
+        debug!("Using last good snapshot at {}", path.display());
+        ConnectionsCache::load(&path)
+            .map_err(|err| {
+                debug!("Failed to read last good snapshot: {:#}", err);
+                err
+            })
+            .unwrap_or_default()
+    }
+
+    /// Load the cache for the configuration.
+    ///
+    /// If there's no cache for multiple configuration files yet, merge the
+    /// caches of every single configuration file instead.
     fn load_cache(&self) -> ConnectionsCache {
         if self.fresh {
             debug!("Cache discarded per command line arguments");
+            return ConnectionsCache::default();
+        }
+        let path = self.cache_path();
+        debug!("Using cache at {}", path.display());
+        match ConnectionsCache::load(&path) {
+            Ok(cache) => cache,
+            Err(err) if self.cache.is_none() && 1 < self.config.len() => {
+                debug!("Failed to read cached connections: {:#}", err);
+                self.config
+                    .iter()
+                    .filter_map(|file| {
+                        let path = cache_path(std::slice::from_ref(file));
+                        debug!("Merging cache at {}", path.display());
+                        ConnectionsCache::load(&path)
+                            .map_err(|err| {
+                                debug!("Failed to read cached connections: {:#}", err);
+                            })
+                            .ok()
+                    })
+                    .fold(ConnectionsCache::default(), ConnectionsCache::merge)
+            }
+            Err(err) => {
+                debug!("Failed to read cached connections: {:#}", err);
+                ConnectionsCache::default()
+            }
+        }
+    }
+}
+
+/// How to mark `connection` of the `desired` route in `cache`.
+fn connection_marks(
+    cache: &ConnectionsCache,
+    desired: &DesiredConnection,
+    connection: &Connection,
+) -> ConnectionMarks {
+    ConnectionMarks {
+        highlight: is_preferred_connection(desired, connection),
+        unexpected_start: cache.has_unexpected_start(desired, connection),
+        tint: desired.color.map(Into::into),
+    }
+}
+
+/// Get the onward connection for every connection of a route with an onward stop.
+///
+/// Get the first connection from the destination to the onward stop
+/// departing after the connection arrives.  Resolve every station only once,
+/// and get all onward connections concurrently.  Skip onward connections
+/// which fail to load, to still show the connections themselves.
+fn get_onward_connections(
+    rt: &tokio::runtime::Runtime,
+    mvg: &Mvg,
+    connections: &[&(&DesiredConnection, &Connection)],
+) -> Vec<Option<Connection>> {
+    let mut names: Vec<&str> = Vec::new();
+    for (desired, _) in connections {
+        if let Some(onward) = &desired.onward {
+            for name in [desired.destination.as_str(), onward.as_str()] {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+    rt.block_on(
+        async {
+            let stations = join_all(names.into_iter().map(|name| async move {
+                (name, mvg.find_unambiguous_station_by_name(name).await)
+            }))
+            .await
+            .into_iter()
+            .filter_map(|(name, station)| {
+                station
+                    .map_err(|error| {
+                        warn!(
+                            "Failed to find station {} for onward connections: {:#}",
+                            name, error
+                        )
+                    })
+                    .ok()
+                    .map(|station| (name, station))
+            })
+            .collect::<HashMap<_, _>>();
+            join_all(connections.iter().map(|(desired, connection)| {
+                let stations = &stations;
+                async move {
+                    let onward = desired.onward.as_ref()?;
+                    let start = stations.get(desired.destination.as_str())?;
+                    let destination = stations.get(onward.as_str())?;
+                    let options = RoutingOptions {
+                        max_connections: Some(1),
+                        ..RoutingOptions::departing_at(
+                            connection.actual_arrival_time().with_timezone(&Utc),
+                        )
+                    };
+                    mvg.get_connections_with_options(start, destination, &options)
+                        .await
+                        .map_err(|error| {
+                            warn!(
+                                "Failed to get onward connection from {} to {}: {:#}",
+                                desired.destination, onward, error
+                            )
+                        })
+                        .ok()?
+                        .into_iter()
+                        .next()
+                }
+            }))
+            .await
+        }
+        .in_current_span(),
+    )
+}
+
+/// Exit code if the run succeeded, but found no reachable connection.
+const EXIT_NO_CONNECTIONS: u8 = 4;
+
+/// Exit code if the run succeeded, but some route has no connections, with `--fail-on-empty`.
+const EXIT_EMPTY_ROUTE: u8 = 5;
+
+/// The number of columns of the terminal on standard output.
+///
+/// `None` if standard output is no terminal.
+fn terminal_width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let size = terminal_size::terminal_size_of(std::io::stdout());
+    if size.is_none() {
+        debug!("Failed to get terminal size");
+    }
+    size.map(|(terminal_size::Width(width), _)| usize::from(width))
+}
+
+/// Describe the version of this program.
+///
+/// If `verbose` also include the git commit and date it was built from, and
+/// the MVG API it talks to.
+fn version_info(verbose: bool) -> String {
+    let version = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    if verbose {
+        format!(
+            "{version}\ncommit: {}\nbuilt: {}\nMVG API: {API_BASE_URL}",
+            option_env!("HOME_GIT_COMMIT").unwrap_or("unknown"),
+            option_env!("HOME_BUILD_DATE").unwrap_or("unknown"),
+        )
+    } else {
+        version
+    }
+}
+
+/// Show connections of all routes in `config` for every start time in `args`.
+///
+/// Round every start time down to the minute, and show only routes active at
+/// that time.  Resolve the stations of all routes only once, and then get
+/// connections for all start times concurrently.
+///
+/// Neither use nor update the cache, because it holds connections for a
+/// single start time, and connections for other start times would replace
+/// the connections for the next regular invocation.
+fn show_start_times<F>(
+    rt: &tokio::runtime::Runtime,
+    args: &Arguments,
+    config: &Config,
+    reachability: Reachability,
+    is_shown: F,
+    display_options: &DisplayOptions,
+) -> Result<ExitCode>
+where
+    F: Fn(&Connection) -> bool,
+{
+    let buckets = args
+        .start_times
+        .iter()
+        .map(|start_time| {
+            start_time
+                .with_second(0)
+                .and_then(|time| time.with_nanosecond(0))
+                .unwrap_or(*start_time)
+        })
+        .collect::<BTreeSet<_>>();
+    let configs = buckets
+        .iter()
+        .map(|bucket| {
+            let connections = config
+                .connections
+                .iter()
+                .filter(|desired| desired.is_active_at(bucket.naive_local()))
+                .cloned()
+                .collect();
+            (
+                *bucket,
+                Config {
+                    connections,
+                    ..config.clone()
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mvg = rt.block_on(args.mvg().in_current_span())?;
+    let mut names: Vec<String> = Vec::new();
+    for (_, config) in &configs {
+        for desired in &config.connections {
+            for name in [&desired.start, &desired.destination] {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+    }
+    let caches = rt.block_on(
+        async {
+            let stations = try_join_all(names.into_iter().map(|name| {
+                let mvg = &mvg;
+                async move {
+                    let station = mvg.find_unambiguous_station_by_name(&name).await?;
+                    Ok::<_, anyhow::Error>((name, station))
+                }
+            }))
+            .await?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+            try_join_all(configs.into_iter().map(|(bucket, config)| {
+                let (mvg, stations) = (&mvg, &stations);
+                async move {
+                    let desired_start_time = bucket.with_timezone(&Utc);
+                    let cache = ConnectionsCache::default()
+                        .update_config(config)
+                        .refresh_empty::<anyhow::Error, _, _>(desired_start_time, |desired| async {
+                            let start = stations[desired.start.as_str()].clone();
+                            let destination = &stations[desired.destination.as_str()];
+                            let connections = mvg
+                                .get_connections(
+                                    &start,
+                                    destination,
+                                    desired_start_time + desired.walk_to_start,
+                                )
+                                .await?;
+                            Ok((desired, start, connections))
+                        })
+                        .await?
+                        .evict_unreachable_connections(desired_start_time, reachability)
+                        .evict_unusable_connections();
+                    Ok::<_, anyhow::Error>((bucket, cache))
+                }
+            }))
+            .await
+        }
+        .in_current_span(),
+    )?;
+
+    let mut no_connections = true;
+    for (start_time, cache) in &caches {
+        println!("{}:", start_time.format("%Y-%m-%d %H:%M"));
+        for (desired, connection) in cache
+            .all_connections()
+            .into_iter()
+            .filter(|(_, c)| is_shown(c))
+            .take(args.connections as usize)
+        {
+            no_connections = false;
+            println!(
+                "{}",
+                display_with_walk_time(
+                    connection,
+                    desired.walk_to_start,
+                    connection_marks(cache, desired, connection),
+                    display_options,
+                )
+            );
+        }
+    }
+
+    if no_connections {
+        Ok(ExitCode::from(EXIT_NO_CONNECTIONS))
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Show connections of the day starting at `start_time`, grouped by hour of departure.
+///
+/// Get connections page by page, starting every page at the last departure
+/// of the previous page, until connections depart on the next day.
+///
+/// Neither use nor update the cache, and do not evict unreachable
+/// connections, because a plan for later does not depend on the time to walk
+/// to the start now.
+fn show_day<F>(
+    rt: &tokio::runtime::Runtime,
+    args: &Arguments,
+    config: &Config,
+    start_time: DateTime<Local>,
+    is_shown: F,
+    display_options: &DisplayOptions,
+) -> Result<ExitCode>
+where
+    F: Fn(&Connection) -> bool,
+{
+    let mvg = rt.block_on(args.mvg().in_current_span())?;
+    let desired_start_time = start_time.with_timezone(&Utc);
+    let next_day = (start_time.date_naive() + Duration::days(1)).and_time(NaiveTime::MIN);
+    let end_of_day = Local
+        .from_local_datetime(&next_day)
+        .earliest()
+        .with_context(|| format!("{} does not exist in the local time zone", next_day))?;
+    let cache = rt
+        .block_on(
             ConnectionsCache::default()
-        } else {
-            debug!("Using cache");
-            ConnectionsCache::load()
-                .map_err(|err| {
-                    debug!("Failed to read cached connections: {:#}", err);
-                    err
+                .update_config(config.clone())
+                .refresh_empty::<anyhow::Error, _, _>(desired_start_time, |desired| async {
+                    let (start, destination) = try_join(
+                        mvg.find_unambiguous_station_by_name(&desired.start),
+                        mvg.find_unambiguous_station_by_name(&desired.destination),
+                    )
+                    .await?;
+                    let mut connections: Vec<Connection> = Vec::new();
+                    let mut time = desired_start_time + desired.walk_to_start;
+                    loop {
+                        let page = mvg.get_connections(&start, &destination, time).await?;
+                        let last_departure = page.iter().map(|c| c.planned_departure_time()).max();
+                        let new_connections = page
+                            .into_iter()
+                            .filter(|c| {
+                                let signature = c.signature();
+                                !connections
+                                    .iter()
+                                    .any(|known| known.signature() == signature)
+                            })
+                            .collect::<Vec<_>>();
+                        let Some(last_departure) =
+                            last_departure.filter(|_| !new_connections.is_empty())
+                        else {
+                            break;
+                        };
+                        connections.extend(new_connections);
+                        if end_of_day <= last_departure {
+                            break;
+                        }
+                        time = last_departure.with_timezone(&Utc);
+                    }
+                    Ok((desired, start, connections))
                 })
-                .unwrap_or_default()
+                .in_current_span(),
+        )?
+        .evict_unusable_connections();
+
+    let connections = cache
+        .all_connections()
+        .into_iter()
+        .filter(|(_, c)| is_shown(c))
+        .filter(|(_, c)| {
+            let departure = c.planned_departure_time().with_timezone(&Local);
+            start_time <= departure && departure.date_naive() == start_time.date_naive()
+        })
+        .collect::<Vec<_>>();
+    for (hour, connections) in by_departure_hour(&connections) {
+        println!("{}:", hour.format("%Y-%m-%d %H:00"));
+        for (desired, connection) in connections {
+            println!(
+                "{}",
+                display_with_walk_time(
+                    connection,
+                    desired.walk_to_start,
+                    connection_marks(&cache, desired, connection),
+                    display_options,
+                )
+            );
         }
     }
-}
 
-fn process_args(args: Arguments) -> Result<()> {
-    let config = match &args.config {
-        Some(file) => Config::from_file(file)?,
-        None => Config::from_default_location()?,
-    };
+    if connections.is_empty() {
+        Ok(ExitCode::from(EXIT_NO_CONNECTIONS))
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
 
-    let desired_start_time = args.start_time.with_timezone(&Utc);
+fn process_args(mut args: Arguments) -> Result<ExitCode> {
+    if args.version {
+        println!("{}", version_info(args.verbose));
+        return Ok(ExitCode::SUCCESS);
+    }
 
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .unwrap();
 
-    let cache = args.load_cache().update_config(config);
+    if args.ping {
+        // Use the proxy settings of the configuration, if there is one
+        if let Ok(config) = args.load_config() {
+            args.use_proxy_settings(&config);
+        }
+        let mvg = rt.block_on(args.mvg().in_current_span())?;
+        rt.block_on(mvg.healthcheck().in_current_span())?;
+        println!("MVG API reachable");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::PruneCache) = args.command {
+        let removed = prune_cache_files()?;
+        for (path, _) in &removed {
+            println!("Removed {}", path.display());
+        }
+        let freed: u64 = removed.iter().map(|(_, size)| size).sum();
+        println!("Freed {} bytes", freed);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.list_caches {
+        let current = args.cache_path();
+        for path in cache_files()? {
+            let marker = if path == current { " (current)" } else { "" };
+            println!("{}{}", path.display(), marker);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.print_config_path {
+        let paths = if args.config.is_empty() {
+            vec![Config::default_path()?]
+        } else {
+            args.config.clone()
+        };
+        for path in paths {
+            let path = std::path::absolute(&path).with_context(|| {
+                format!("Failed to resolve configuration path {}", path.display())
+            })?;
+            println!("{}", path.display());
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let ad_hoc_config = args.ad_hoc_config()?;
+    // Ad-hoc routes have nothing in common with the configured routes, so
+    // neither use nor update the cache for them.
+    let ad_hoc = ad_hoc_config.is_some();
+    let config = if let Some(config) = ad_hoc_config {
+        config
+    } else {
+        args.load_config()?
+    };
+
+    if let Some(path) = &args.write_config {
+        config.to_file(path)?;
+        println!("Wrote configuration to {}", path.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let config = config.round_walk_to_start();
+
+    args.use_proxy_settings(&config);
+
+    if let Some(pattern) = &args.route {
+        if !config.connections.iter().any(|c| c.matches_route(pattern)) {
+            return Err(anyhow!("No route matches {}", pattern));
+        }
+    }
+
+    let home = config.home;
+    let max_total_walk = args.max_total_walk.or(config.max_total_walk);
+
+    let reachability =
+        if args.only_reachable_now || (config.strict_reachable && !args.include_barely_reachable) {
+            Reachability::Strict
+        } else {
+            Reachability::Lenient
+        };
+
+    let now = Local::now();
+    let day_start = args
+        .day
+        .map(|day| {
+            Local
+                .from_local_datetime(&day)
+                .earliest()
+                .with_context(|| format!("{} does not exist in the local time zone", day))
+        })
+        .transpose()?;
+    let start_time = day_start.or(args.start_time).unwrap_or(now);
+    let start_in_past = start_time < now;
+    if start_in_past {
+        warn!(
+            "Start time {} is in the past, showing connections from then",
+            start_time.format("%Y-%m-%d %H:%M")
+        );
+    }
+    let desired_start_time = start_time.with_timezone(&Utc);
+
+    let arrival_deadline = args
+        .arrive_before
+        .map(|time| next_local_time(start_time, time));
+    let departure_deadline = args.horizon.map(|horizon| start_time + horizon);
+    let is_shown = |c: &Connection| {
+        let transport_type = c.departure().line_transport_type();
+        !args.exclude_transports.contains(&transport_type)
+            && (!args.rail_only || transport_type.is_rail())
+            && (!args.bus_only || transport_type.is_bus())
+            && (args.include_on_demand || !c.is_on_demand())
+            && max_total_walk.is_none_or(|max| c.walking_time() <= max)
+            && args.max_trip.is_none_or(|max| c.trip_duration() <= max)
+            && arrival_deadline.is_none_or(|deadline| c.planned_arrival_time() < deadline)
+            && departure_deadline.is_none_or(|deadline| c.planned_departure_time() <= deadline)
+    };
+
+    let display_options = DisplayOptions {
+        seconds: args.seconds,
+        compact: args.compact,
+        legs: args.legs,
+        stops: args.show_stops,
+        home,
+        relative: args.relative,
+        theme: if args.no_emoji {
+            Theme::Ascii
+        } else {
+            Theme::Emoji
+        },
+        time_format: Some(args.time_format.clone()),
+        max_width: if args.no_truncate {
+            None
+        } else {
+            terminal_width()
+        },
+    };
+
+    // Every start time has routes of its own active at that time
+    if !args.start_times.is_empty() {
+        return show_start_times(
+            &rt,
+            &args,
+            &config,
+            reachability,
+            is_shown,
+            &display_options,
+        );
+    }
+
+    let config = Config {
+        connections: config
+            .connections
+            .into_iter()
+            .filter(|desired| {
+                let active = desired.is_active_at(start_time.naive_local());
+                if !active {
+                    debug!(
+                        "Skipping route from {} to {}, not active at {}",
+                        desired.start, desired.destination, start_time
+                    );
+                }
+                active
+            })
+            .collect(),
+        ..config
+    };
+
+    if args.day.is_some() {
+        return show_day(&rt, &args, &config, start_time, is_shown, &display_options);
+    }
+
+    if args.raw {
+        let mvg = rt.block_on(args.mvg().in_current_span())?;
+        for desired in &config.connections {
+            let body = rt.block_on(
+                async {
+                    let start = mvg.find_unambiguous_station_by_name(&desired.start).await?;
+                    let destination = mvg
+                        .find_unambiguous_station_by_name(&desired.destination)
+                        .await?;
+                    let options =
+                        RoutingOptions::departing_at(desired_start_time + desired.walk_to_start);
+                    mvg.get_raw_connections_with_options(&start, &destination, &options)
+                        .await
+                }
+                .in_current_span(),
+            )?;
+            println!("{}", body);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Connections for a start time in the past have nothing in common with
+    // the cached connections, so neither use nor update the cache.
+    let cache = if ad_hoc || start_in_past {
+        ConnectionsCache::default()
+    } else {
+        args.load_cache()
+    }
+    .update_config(config)
+    .compact();
     event!(
         Level::INFO,
         "Found {} connections in cache for current configuration",
         cache.all_connections().len()
     );
 
-    let new_cache = if args.dump_cache {
-        cache
+    if args.explain_eviction {
+        for (desired, connection, fate) in cache.explain_eviction(desired_start_time, reachability)
+        {
+            let tag = match fate {
+                Some(reason) => format!("evicted ({reason})"),
+                None => "kept".to_string(),
+            };
+            println!(
+                "{:<30} {}",
+                tag,
+                display_with_walk_time(
+                    connection,
+                    desired.walk_to_start,
+                    connection_marks(&cache, desired, connection),
+                    &display_options,
+                )
+            );
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Keep the client to reuse it for onward connections
+    let (mvg, new_cache, lacks_realtime) = if args.dump_cache {
+        (None, cache, false)
+    } else if args.offline {
+        let new_cache = cache
+            .fill_empty_from(args.load_last_good())
+            .evict_unreachable_connections(desired_start_time, reachability)
+            .evict_unusable_connections();
+        (None, new_cache, false)
     } else {
         let number_of_cached_connections = cache.all_connections().len();
         let cleared_cache = cache
-            .evict_unreachable_connections(desired_start_time)
-            .evict_too_few_connections(3);
+            // Connections requested for a later start time miss connections from the desired start time
+            .evict_requested_after(desired_start_time)
+            .evict_unreachable_connections(desired_start_time, reachability)
+            .evict_too_few_connections(MIN_CACHED_CONNECTIONS);
+        let cleared_cache = if args.refresh_all {
+            cleared_cache.evict_all_connections()
+        } else {
+            cleared_cache
+        };
+        let cleared_cache = match &args.route {
+            Some(pattern) => cleared_cache.evict_routes_matching(pattern),
+            None => cleared_cache,
+        };
+        // --route already refreshes all matching routes
+        let cleared_cache = match (args.track, &args.route) {
+            (Some(time), None) => cleared_cache.evict_routes_departing_at(time),
+            _ => cleared_cache,
+        };
         event!(
             Level::INFO,
             "{} connections remained in cache after eviction, evicted {} connections",
@@ -183,46 +1097,259 @@ fn process_args(args: Arguments) -> Result<()> {
         );
 
         // Create single client upfront; this resolves the HTTP proxy (if any) only once.
-        let mvg = rt.block_on(Mvg::new().in_current_span())?;
+        let mvg = rt.block_on(args.mvg().in_current_span())?;
 
-        rt.block_on(
+        // Keep the cached connections to fall back to if the MVG API is unreachable
+        let refreshed = rt.block_on(
             cleared_cache
-                .refresh_empty::<anyhow::Error, _, _>(|desired| async {
-                    let desired_departure_time = desired_start_time + desired.walk_to_start;
-                    let start = mvg.find_unambiguous_station_by_name(&desired.start).await?;
-                    let destination = mvg
-                        .find_unambiguous_station_by_name(&desired.destination)
+                .clone()
+                .refresh_empty::<anyhow::Error, _, _>(desired_start_time, |desired| async {
+                    let (start, connections) = mvg
+                        .get_connections_between_names(
+                            &desired.start,
+                            &desired.destination,
+                            desired_start_time + desired.walk_to_start,
+                        )
                         .await?;
-                    let connections = mvg
-                        .get_connections(&start, &destination, desired_departure_time)
-                        .await?;
-                    Ok((desired, connections))
+                    Ok((desired, start, connections))
                 })
                 .in_current_span(),
-        )?
-        // Evict unreachable connections again, in case the MVG API returned nonsense
-        .evict_unreachable_connections(desired_start_time)
-        // And evict anything that starts with walking
-        .evict_starts_with_pedestrian()
+        );
+        let (refreshed, lacks_realtime) = match refreshed {
+            Ok(cache) => {
+                // Only connections fetched just now tell whether real time information is available
+                let lacks_realtime = cache.lacks_realtime_information(|desired| {
+                    cleared_cache
+                        .routes()
+                        .any(|(cached, connections)| cached == desired && connections.is_empty())
+                });
+                (cache, lacks_realtime)
+            }
+            Err(error) => {
+                if is_network_error(&error) {
+                    warn!(
+                        "MVG API not reachable, showing cached connections only: {:#}",
+                        error
+                    );
+                } else {
+                    warn!(
+                        "Failed to refresh connections, showing cached connections only: {:#}",
+                        error
+                    );
+                }
+                // Fall back to the connections we showed last for routes without cached connections,
+                // and fail only if no route has any connection to show.
+                let fallback = cleared_cache.fill_empty_from(args.load_last_good());
+                if fallback.all_connections().is_empty() {
+                    return Err(error);
+                }
+                (fallback, false)
+            }
+        };
+        for desired in refreshed.routes_too_far_to_walk(desired_start_time, reachability) {
+            eprintln!(
+                "No connection from {} to {} leaves enough time to walk to the start; is walk_to_start too large?",
+                desired.start, desired.destination
+            );
+        }
+        let new_cache = refreshed
+            // Evict unreachable connections again, in case the MVG API returned nonsense
+            .evict_unreachable_connections(desired_start_time, reachability)
+            // And anything that walks to an adjacent stop, starts with walking, or doesn't run at all
+            .evict_unusable_connections();
+        (Some(mvg), new_cache, lacks_realtime)
+    };
+
+    // Save the cache only once, after the final eviction, and along with the
+    // connections shown by this run, if any.
+    let save_cache = |cache: &ConnectionsCache| {
+        if ad_hoc {
+            debug!("Not saving cache for ad-hoc routes");
+        } else if start_in_past {
+            debug!("Not saving cache for start time in the past");
+        } else if args.no_save {
+            debug!("Not saving cache per command line arguments");
+        } else {
+            debug!("Saving cache");
+            if let Err(error) = cache.save(&args.cache_path()) {
+                warn!("Failed to save cached connections: {:#}", error);
+            }
+        }
     };
 
-    debug!("Saving cache");
-    if let Err(error) = new_cache.save() {
-        warn!("Failed to save cached connections: {:#}", error);
+    if args.count {
+        let count = new_cache
+            .all_connections()
+            .into_iter()
+            .filter(|(_, c)| is_shown(c))
+            .filter(|(_, c)| !args.only_delayed || c.is_delayed())
+            .count();
+        println!("{}", count);
+        save_cache(&new_cache);
+        return Ok(ExitCode::SUCCESS);
     }
 
-    for (walk_to_start, connection) in new_cache
-        .all_connections()
+    // Tell on stderr, to keep the output of e.g. --summary intact
+    if !start_in_past && lacks_realtime {
+        eprintln!("{}", display_realtime_unavailable(&display_options));
+    }
+
+    if args.summary {
+        let earliest = new_cache
+            .earliest_connections(is_shown)
+            .into_iter()
+            .map(|(desired, c)| (desired.walk_to_start, c))
+            .collect::<Vec<_>>();
+        println!("{}", display_summary(&earliest, &display_options));
+    }
+
+    let is_listed = |c: &Connection| is_shown(c) && (!args.only_delayed || c.is_delayed());
+    let is_tracked = |desired: &DesiredConnection, c: &Connection| {
+        args.track.is_some_and(|time| departs_at(c, time))
+            && args
+                .route
+                .as_ref()
+                .is_none_or(|pattern| desired.matches_route(pattern))
+    };
+    // Filter before limiting connections per route, so that hidden connections
+    // do not take the place of connections we'd show.
+    let mut connections = if args.track.is_some() {
+        new_cache
+            .all_connections()
+            .into_iter()
+            .filter(|(desired, c)| is_tracked(desired, c) && is_listed(c))
+            .collect()
+    } else if args.earliest_only {
+        new_cache.earliest_connections(is_listed)
+    } else if let Some(limit) = args.max_per_route {
+        new_cache.connections_limited_per_route(limit, is_listed)
+    } else {
+        new_cache
+            .all_connections()
+            .into_iter()
+            .filter(|(_, c)| is_listed(c))
+            .collect()
+    };
+    if args.prefer_direct {
+        prefer_direct_connections(&mut connections, Duration::minutes(5));
+    }
+    match args.sort {
+        SortOrder::Earliest => {}
+        SortOrder::Latest => latest_leave_first(&mut connections),
+        SortOrder::Actual => actual_leave_first(&mut connections),
+    }
+    if let Some(time) = args.track {
+        connections.truncate(1);
+        if connections.is_empty() {
+            let hidden = new_cache
+                .all_connections()
+                .into_iter()
+                .any(|(desired, c)| is_tracked(desired, c));
+            save_cache(&new_cache);
+            return Err(if hidden {
+                anyhow!(
+                    "Connection departing at {} hidden by filters",
+                    time.format("%H:%M")
+                )
+            } else {
+                anyhow!("No connection departing at {}", time.format("%H:%M"))
+            });
+        }
+    }
+    let shown_connections = connections
         .iter()
+        .filter(|(desired, c)| !args.since_last || !new_cache.was_shown(desired, c))
         .take(args.connections as usize)
-    {
-        println!("{}", display_with_walk_time(connection, *walk_to_start));
+        .collect::<Vec<_>>();
+    let needs_onward = !args.offline
+        && shown_connections
+            .iter()
+            .any(|(desired, _)| desired.onward.is_some());
+    let onward_connections = if needs_onward {
+        let mvg = match mvg {
+            Some(mvg) => Ok(mvg),
+            None => rt.block_on(args.mvg().in_current_span()),
+        };
+        match mvg {
+            Ok(mvg) => get_onward_connections(&rt, &mvg, &shown_connections),
+            Err(error) => {
+                warn!("Failed to get onward connections: {:#}", error);
+                vec![None; shown_connections.len()]
+            }
+        }
+    } else {
+        vec![None; shown_connections.len()]
+    };
+    for ((desired, connection), onward) in shown_connections.iter().zip(&onward_connections) {
+        println!(
+            "{}{}",
+            // Label connections with their destination, to tell ad-hoc routes apart
+            if ad_hoc {
+                format!("{}: ", desired.destination)
+            } else {
+                String::new()
+            },
+            display_with_walk_time(
+                *connection,
+                desired.walk_to_start,
+                connection_marks(&new_cache, desired, connection),
+                &display_options,
+            )
+        );
+        if let Some(onward) = onward {
+            println!("{}", display_onward(onward, &display_options));
+        }
+    }
+
+    let no_connections = shown_connections.is_empty();
+    // Connections exist, but none runs late; look at connections before
+    // hiding those shown before, which may well run late.
+    let all_on_time = args.only_delayed
+        && connections.is_empty()
+        && new_cache
+            .all_connections()
+            .into_iter()
+            .any(|(_, c)| is_shown(c));
+    if all_on_time {
+        println!("All connections on time");
+    }
+    let empty_routes = new_cache
+        .routes()
+        .filter(|(_, connections)| connections.is_empty())
+        .map(|(desired, _)| format!("{} to {}", desired.start, desired.destination))
+        .collect::<Vec<_>>();
+    if !no_connections && !start_in_past && !ad_hoc && !args.no_save {
+        debug!("Saving last good snapshot");
+        if let Err(error) = new_cache.save(&last_good_path(&args.cache_path())) {
+            warn!("Failed to save last good snapshot: {:#}", error);
+        }
     }
 
-    Ok(())
+    if args.since_last {
+        // Remember the connections we showed in addition to those shown
+        // before, so that the next run only shows connections not seen yet.
+        let keys = shown_connections
+            .iter()
+            .map(|(desired, c)| connection_key(desired, c))
+            .collect();
+        save_cache(&new_cache.add_shown(keys));
+    } else {
+        save_cache(&new_cache);
+    }
+
+    if args.fail_on_empty && !empty_routes.is_empty() {
+        for route in &empty_routes {
+            eprintln!("No connections from {}", route);
+        }
+        Ok(ExitCode::from(EXIT_EMPTY_ROUTE))
+    } else if no_connections && !all_on_time {
+        Ok(ExitCode::from(EXIT_NO_CONNECTIONS))
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
 }
 
-fn main() {
+fn main() -> ExitCode {
     tracing_subscriber::registry()
         .with(fmt::layer().pretty())
         .with(
@@ -233,8 +1360,11 @@ fn main() {
         .init();
 
     let args = Arguments::parse();
-    if let Err(err) = process_args(args) {
-        eprintln!("{:#}", err);
-        std::process::exit(1);
+    match process_args(args) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{:#}", err);
+            ExitCode::FAILURE
+        }
     }
 }
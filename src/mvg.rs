@@ -4,11 +4,14 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::fmt::{Display, Formatter};
 use std::ops::Deref;
+use std::str::FromStr;
 
 use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Duration, FixedOffset, Utc};
-use reqwest::{Client, Proxy, Url};
+use futures::future::try_join;
+use reqwest::{Client, ClientBuilder, Proxy, Response, Url};
 use serde::{Deserialize, Serialize};
 use tracing::{event, instrument, span, Instrument, Level};
 
@@ -23,6 +26,13 @@ pub struct Station {
     name: String,
 }
 
+impl Station {
+    /// The global ID of this station, e.g. `de:09162:6`.
+    pub fn global_id(&self) -> &str {
+        &self.global_id
+    }
+}
+
 impl Place for Station {
     fn name(&self) -> &str {
         &self.name
@@ -63,9 +73,37 @@ pub enum TransportType {
 }
 
 impl TransportType {
+    /// All transport types.
+    pub const ALL: [TransportType; 9] = [
+        TransportType::Schiff,
+        TransportType::Ruftaxi,
+        TransportType::Bahn,
+        TransportType::UBahn,
+        TransportType::Tram,
+        TransportType::SBahn,
+        TransportType::Bus,
+        TransportType::RegionalBus,
+        TransportType::Pedestrian,
+    ];
+
+    /// The name of this transport type in the MVG API.
+    pub fn api_name(self) -> &'static str {
+        match self {
+            TransportType::Schiff => "SCHIFF",
+            TransportType::Ruftaxi => "RUFTAXI",
+            TransportType::Bahn => "BAHN",
+            TransportType::UBahn => "UBAHN",
+            TransportType::Tram => "TRAM",
+            TransportType::SBahn => "SBAHN",
+            TransportType::Bus => "BUS",
+            TransportType::RegionalBus => "REGIONAL_BUS",
+            TransportType::Pedestrian => "PEDESTRIAN",
+        }
+    }
+
     pub fn icon(self) -> &'static str {
         match self {
-            TransportType::Bahn => "🚆",
+            TransportType::Bahn => "🚄",
             TransportType::SBahn => "🚆",
             TransportType::UBahn => "🚇",
             TransportType::Tram => "🚊",
@@ -76,6 +114,85 @@ impl TransportType {
             TransportType::Pedestrian => "🚶",
         }
     }
+
+    /// Whether this transport type runs on rails.
+    pub fn is_rail(self) -> bool {
+        matches!(
+            self,
+            TransportType::Bahn | TransportType::SBahn | TransportType::UBahn | TransportType::Tram
+        )
+    }
+
+    /// Whether this transport type is a bus.
+    pub fn is_bus(self) -> bool {
+        matches!(self, TransportType::Bus | TransportType::RegionalBus)
+    }
+
+    /// Whether this transport type runs on demand only, and requires booking ahead.
+    pub fn is_on_demand(self) -> bool {
+        matches!(self, TransportType::Ruftaxi)
+    }
+}
+
+impl FromStr for TransportType {
+    type Err = anyhow::Error;
+
+    /// Parse a transport type from its API name or its display name.
+    ///
+    /// Ignore case, dashes and underscores, to accept `ubahn`, `u-bahn`,
+    /// `UBAHN`, `regional-bus` and `REGIONAL_BUS` alike.
+    fn from_str(s: &str) -> Result<Self> {
+        let normalize = |name: &str| {
+            name.chars()
+                .filter(|c| *c != '-' && *c != '_')
+                .collect::<String>()
+                .to_lowercase()
+        };
+        let name = normalize(s);
+        TransportType::ALL
+            .into_iter()
+            .find(|t| normalize(t.api_name()) == name)
+            .ok_or_else(|| anyhow!("Unknown transport type: {}", s))
+    }
+}
+
+impl Display for TransportType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TransportType::Schiff => "Schiff",
+            TransportType::Ruftaxi => "Ruftaxi",
+            TransportType::Bahn => "Bahn",
+            TransportType::UBahn => "U-Bahn",
+            TransportType::Tram => "Tram",
+            TransportType::SBahn => "S-Bahn",
+            TransportType::Bus => "Bus",
+            TransportType::RegionalBus => "Regional-Bus",
+            TransportType::Pedestrian => "Pedestrian",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A point on earth.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Coordinates {
+    /// The latitude in degrees.
+    pub latitude: f64,
+    /// The longitude in degrees.
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    /// The great-circle distance to `other`, in meters.
+    pub fn distance_to(&self, other: &Coordinates) -> f64 {
+        const EARTH_RADIUS_IN_METERS: f64 = 6_371_000.0;
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let delta_lat = lat2 - lat1;
+        let delta_lon = (other.longitude - self.longitude).to_radians();
+        let a = (delta_lat / 2.0).sin().powi(2)
+            + lat1.cos() * lat2.cos() * (delta_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -83,12 +200,37 @@ impl TransportType {
 pub struct ConnectionPartStop {
     name: String,
     planned_departure: DateTime<FixedOffset>,
+    /// The planned arrival at this stop.
+    ///
+    /// Absent in many responses of the MVG API, which then use
+    /// `planned_departure` for the arrival as well.
+    #[serde(default)]
+    planned_arrival: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    latitude: Option<f64>,
+    #[serde(default)]
+    longitude: Option<f64>,
 }
 
 impl ConnectionPartStop {
-    fn planned_departure(&self) -> DateTime<FixedOffset> {
+    pub fn planned_departure(&self) -> DateTime<FixedOffset> {
         self.planned_departure
     }
+
+    /// The planned arrival at this stop.
+    ///
+    /// Fall back to the planned departure if the API did not tell the arrival.
+    pub fn planned_arrival(&self) -> DateTime<FixedOffset> {
+        self.planned_arrival.unwrap_or(self.planned_departure)
+    }
+
+    /// The coordinates of this stop, if known.
+    pub fn coordinates(&self) -> Option<Coordinates> {
+        Some(Coordinates {
+            latitude: self.latitude?,
+            longitude: self.longitude?,
+        })
+    }
 }
 
 impl Place for ConnectionPartStop {
@@ -170,6 +312,11 @@ pub struct ConnectionPart {
     from: ConnectionPartDepartingStop,
     to: ConnectionPartArrivingStop,
     line: Line,
+    #[serde(default, rename = "isCancelled")]
+    cancelled: bool,
+    /// The stops between departure and arrival, if the API told them.
+    #[serde(default)]
+    intermediate_stops: Vec<ConnectionPartStop>,
 }
 
 impl ConnectionPart {
@@ -181,6 +328,13 @@ impl ConnectionPart {
         &self.to
     }
 
+    /// The stops this part passes between departure and arrival.
+    ///
+    /// Empty if the API did not tell the intermediate stops.
+    pub fn intermediate_stops(&self) -> &[ConnectionPartStop] {
+        &self.intermediate_stops
+    }
+
     pub fn line_transport_type(&self) -> TransportType {
         self.line.transport_type
     }
@@ -188,6 +342,31 @@ impl ConnectionPart {
     pub fn line_label(&self) -> &str {
         &self.line.label
     }
+
+    /// Whether this part is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// The departure delay of this part, if real time information is available.
+    pub fn departure_delay(&self) -> Option<Duration> {
+        self.from.departure_delay()
+    }
+
+    /// The actual departure time of this part, including delays.
+    pub fn actual_departure_time(&self) -> DateTime<FixedOffset> {
+        self.from.planned_departure() + self.departure_delay().unwrap_or(Duration::zero())
+    }
+
+    /// The arrival delay of this part, if real time information is available.
+    pub fn arrival_delay(&self) -> Option<Duration> {
+        self.to.arrival_delay()
+    }
+
+    /// The actual arrival time of this part, including delays.
+    pub fn actual_arrival_time(&self) -> DateTime<FixedOffset> {
+        self.to.planned_arrival() + self.arrival_delay().unwrap_or(Duration::zero())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -226,63 +405,360 @@ impl Connection {
     }
 
     pub fn planned_arrival_time(&self) -> DateTime<FixedOffset> {
-        self.arrival().to().planned_departure()
+        self.arrival().to().planned_arrival()
     }
 
     pub fn actual_arrival_time(&self) -> DateTime<FixedOffset> {
         self.planned_arrival_time() + self.arrival_delay().unwrap_or(Duration::zero())
     }
+
+    /// Whether any part of this connection is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.parts.iter().any(|p| p.is_cancelled())
+    }
+
+    /// Whether this connection departs or arrives late.
+    pub fn is_delayed(&self) -> bool {
+        [self.departure_delay(), self.arrival_delay()]
+            .into_iter()
+            .flatten()
+            .any(|delay| !delay.is_zero())
+    }
+
+    /// Whether real time information is available for any part of this connection.
+    pub fn has_realtime_information(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|p| p.departure_delay().is_some() || p.arrival_delay().is_some())
+    }
+
+    /// Whether any part of this connection runs on demand only.
+    pub fn is_on_demand(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|p| p.line_transport_type().is_on_demand())
+    }
+
+    /// A key which identifies the trip of this connection across refreshes.
+    ///
+    /// The signature consists of the planned departure, the transport type
+    /// and label of the first line, and the planned arrival, and thus does
+    /// not change with real time information.
+    pub fn signature(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            self.planned_departure_time().to_rfc3339(),
+            self.departure().line_transport_type().api_name(),
+            self.departure().line_label(),
+            self.planned_arrival_time().to_rfc3339()
+        )
+    }
+
+    /// The planned time from departure to arrival of this connection.
+    pub fn trip_duration(&self) -> Duration {
+        self.planned_arrival_time() - self.planned_departure_time()
+    }
+
+    /// The total time spent walking between lines in this connection.
+    pub fn walking_time(&self) -> Duration {
+        self.parts
+            .iter()
+            .filter(|p| p.line_transport_type() == TransportType::Pedestrian)
+            .map(|p| p.to().planned_arrival() - p.from().planned_departure())
+            .fold(Duration::zero(), |total, walk| total + walk)
+    }
+
+    /// The names of the stops this connection changes lines at.
+    ///
+    /// Contains neither the first departure nor the final arrival stop.
+    pub fn transfer_stops(&self) -> impl Iterator<Item = &str> {
+        self.parts.windows(2).flat_map(|parts| {
+            let (arrival, departure) = (parts[0].to().name(), parts[1].from().name());
+            std::iter::once(arrival).chain((arrival != departure).then_some(departure))
+        })
+    }
+
+    /// The number of transfers between lines in this connection.
+    ///
+    /// Walking between lines doesn't count as a separate transfer.
+    pub fn transfers(&self) -> usize {
+        self.parts
+            .iter()
+            .filter(|p| p.line_transport_type() != TransportType::Pedestrian)
+            .count()
+            .saturating_sub(1)
+    }
 }
 
-async fn get_portal_proxy_for_url(url: &Url) -> Result<Option<Url>> {
-    system_proxy::unix::FreedesktopPortalProxyResolver::connect()
-        .await
-        .with_context(|| "Failed to connect to freedesktop proxy portal".to_string())?
-        .lookup(url)
+#[cfg(test)]
+impl Connection {
+    /// Load connections from a recorded MVG API response in `tests/fixtures`.
+    pub fn from_fixture(name: &str) -> Vec<Self> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join(name)
+            .with_extension("json");
+        let contents = std::fs::read_to_string(&path)
+            .unwrap_or_else(|error| panic!("Failed to read {}: {error}", path.display()));
+        serde_json::from_str(&contents)
+            .unwrap_or_else(|error| panic!("Failed to parse {}: {error}", path.display()))
+    }
+
+    /// Create a connection from the given `parts`.
+    pub fn from_parts(parts: Vec<ConnectionPart>) -> Self {
+        assert!(!parts.is_empty(), "Connection without parts");
+        Self { parts }
+    }
+
+    /// Create a connection with a single part, without real time information.
+    pub fn single_part(
+        transport_type: TransportType,
+        label: &str,
+        departure: DateTime<FixedOffset>,
+        arrival: DateTime<FixedOffset>,
+    ) -> Self {
+        Self::from_parts(vec![ConnectionPart::new(
+            transport_type,
+            label,
+            departure,
+            arrival,
+        )])
+    }
+}
+
+#[cfg(test)]
+impl Station {
+    /// Create a station with the given `global_id` and `name`.
+    pub fn new<S: Into<String>, T: Into<String>>(global_id: S, name: T) -> Self {
+        Self {
+            global_id: global_id.into(),
+            name: name.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ConnectionPartStop {
+    /// Create a stop without coordinates.
+    fn new(name: &str, planned_departure: DateTime<FixedOffset>) -> Self {
+        Self {
+            name: name.to_string(),
+            planned_departure,
+            planned_arrival: None,
+            latitude: None,
+            longitude: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl ConnectionPart {
+    /// Create a part from "Start" to "Destination", without real time information.
+    pub fn new(
+        transport_type: TransportType,
+        label: &str,
+        departure: DateTime<FixedOffset>,
+        arrival: DateTime<FixedOffset>,
+    ) -> Self {
+        Self {
+            from: ConnectionPartDepartingStop {
+                stop: ConnectionPartStop::new("Start", departure),
+                departure_delay_in_minutes: None,
+            },
+            to: ConnectionPartArrivingStop {
+                place: ConnectionPartStop::new("Destination", arrival),
+                arrival_delay_in_minutes: None,
+            },
+            line: Line {
+                label: label.to_string(),
+                transport_type,
+            },
+            cancelled: false,
+            intermediate_stops: Vec::new(),
+        }
+    }
+
+    /// Depart at the stop named `from` and arrive at the stop named `to` instead.
+    pub fn between(mut self, from: &str, to: &str) -> Self {
+        self.from.stop.name = from.to_string();
+        self.to.place.name = to.to_string();
+        self
+    }
+
+    /// Pass the stops with the given `names`, all at the time of departure.
+    pub fn via(mut self, names: &[&str]) -> Self {
+        let departure = self.from.planned_departure();
+        self.intermediate_stops = names
+            .iter()
+            .map(|name| ConnectionPartStop::new(name, departure))
+            .collect();
+        self
+    }
+
+    /// Add real time information with the given delays in minutes.
+    pub fn delayed(mut self, departure_delay: i64, arrival_delay: i64) -> Self {
+        self.from.departure_delay_in_minutes = Some(departure_delay);
+        self.to.arrival_delay_in_minutes = Some(arrival_delay);
+        self
+    }
+}
+
+#[cfg(feature = "portal")]
+async fn get_portal_proxy_for_url(url: &Url) -> Option<Url> {
+    event!(
+        Level::DEBUG,
+        "Asking freedesktop proxy portal for proxy for {url}"
+    );
+    let proxy = async {
+        system_proxy::unix::FreedesktopPortalProxyResolver::connect()
+            .await
+            .with_context(|| "Failed to connect to freedesktop proxy portal".to_string())?
+            .lookup(url)
+            .await
+            .with_context(|| format!("Failed to lookup proxy for {}", url))
+    };
+    proxy
         .await
-        .with_context(|| format!("Failed to lookup proxy for {}", url))
+        .map_err(|err| event!(Level::WARN, "Portal proxy lookup failed: {err}"))
+        .ok()
+        .flatten()
+}
+
+#[cfg(not(feature = "portal"))]
+async fn get_portal_proxy_for_url(url: &Url) -> Option<Url> {
+    event!(
+        Level::DEBUG,
+        "Not asking freedesktop proxy portal for proxy for {url}, portal support disabled"
+    );
+    None
 }
 
 async fn get_proxy_for_url(url: &Url) -> Option<Url> {
     event!(Level::DEBUG, "Looking up proxy for {url} in environment");
     if let Some(proxy) = system_proxy::env::from_curl_env().lookup(url) {
         Some(proxy.clone())
+    } else if let Some(proxy) = get_portal_proxy_for_url(url).await {
+        Some(proxy)
     } else {
-        event!(
-            Level::DEBUG,
-            "Asking freedesktop proxy portal for proxy for {url}"
-        );
-        if let Some(proxy) = get_portal_proxy_for_url(url)
-            .await
-            .map_err(|err| event!(Level::WARN, "Portal proxy lookup failed: {err}"))
-            .ok()
-            .flatten()
-        {
-            Some(proxy)
-        } else {
-            event!(Level::DEBUG, "Found no proxy for {url}");
-            None
+        event!(Level::DEBUG, "Found no proxy for {url}");
+        None
+    }
+}
+
+/// The base URL of the MVG API.
+pub const API_BASE_URL: &str = "https://www.mvg.de/api/fib/v2/";
+
+/// Options for routing connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingOptions {
+    /// The time to depart at, or to arrive at if `is_arrival` is set.
+    pub time: DateTime<Utc>,
+    /// Whether to arrive at `time` rather than depart at `time`.
+    pub is_arrival: bool,
+    /// The transport types to use.
+    ///
+    /// [`TransportType::Pedestrian`] is ignored here; use `walking` instead.
+    pub transport_types: Vec<TransportType>,
+    /// Whether to explicitly ask for walking legs.
+    ///
+    /// The MVG API includes walking transfers between lines regardless of
+    /// the requested transport types, so this is not required to get walks
+    /// between stops.  If set, `PEDESTRIAN` is added to the requested
+    /// transport types, which also allows routes that walk all the way.
+    pub walking: bool,
+    /// Return at most this many connections.
+    pub max_connections: Option<usize>,
+}
+
+impl RoutingOptions {
+    /// Depart at `time` with every transport type.
+    pub fn departing_at(time: DateTime<Utc>) -> Self {
+        Self {
+            time,
+            is_arrival: false,
+            transport_types: TransportType::ALL
+                .into_iter()
+                .filter(|t| *t != TransportType::Pedestrian)
+                .collect(),
+            walking: false,
+            max_connections: None,
         }
     }
+
+    /// The transport types as query parameter for the MVG API.
+    fn transport_types_query(&self) -> String {
+        self.transport_types
+            .iter()
+            .filter(|t| **t != TransportType::Pedestrian)
+            .chain(self.walking.then_some(&TransportType::Pedestrian))
+            .map(|t| t.api_name())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Whether `error` was caused by failing to reach the MVG API at all.
+///
+/// This includes failures to connect, e.g. because the network is down, and
+/// timeouts, but not errors returned by the API itself.
+pub fn is_network_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+    })
 }
 
 pub struct Mvg {
     base_url: Url,
+    /// The proxy used for the API, for logging.
+    ///
+    /// `None` for a direct connection.
+    proxy: Option<Url>,
     client: Client,
+    /// A client which connects directly, to retry requests if the proxy fails.
+    direct_client: Option<Client>,
 }
 
 impl Mvg {
-    pub async fn new() -> Result<Self> {
-        let base_url = Url::parse("https://www.mvg.de/api/fib/v2/")?;
+    /// Create a new client for the MVG API from the given client `builder`.
+    ///
+    /// Use `builder` to customize the HTTP client, e.g. to add custom root
+    /// certificates; this function sets the user agent and the proxy.
+    pub async fn with_client_builder(builder: ClientBuilder) -> Result<Self> {
+        let base_url = Url::parse(API_BASE_URL)?;
 
-        let builder = reqwest::ClientBuilder::new().user_agent("home");
+        let builder = builder.user_agent("home");
         // Get the proxy to use for the base API url.  Even though we're technically
         // supposed to resolve the proxy for each URL, it's really unlikely that
         // some PAC thing drills down into the MVG API URLs.
-        let builder = match get_proxy_for_url(&base_url).await {
+        let proxy = get_proxy_for_url(&base_url).await;
+        Self::with_proxy(builder, base_url, proxy)
+    }
+
+    /// Create a new client for the MVG API which always connects directly.
+    ///
+    /// Unlike [`Self::with_client_builder`] do not resolve a proxy, which
+    /// saves asking the proxy portal if the environment sets no proxy.
+    pub fn direct_with_client_builder(builder: ClientBuilder) -> Result<Self> {
+        let base_url = Url::parse(API_BASE_URL)?;
+        Self::with_proxy(builder.user_agent("home"), base_url, None)
+    }
+
+    /// Create a new client for the MVG API which always uses the given `proxy`.
+    ///
+    /// Unlike [`Self::with_client_builder`] do not look up a proxy.
+    pub fn with_fixed_proxy(builder: ClientBuilder, proxy: Url) -> Result<Self> {
+        let base_url = Url::parse(API_BASE_URL)?;
+        Self::with_proxy(builder.user_agent("home"), base_url, Some(proxy))
+    }
+
+    fn with_proxy(builder: ClientBuilder, base_url: Url, proxy: Option<Url>) -> Result<Self> {
+        let builder = match &proxy {
             Some(proxy) => {
                 event!(Level::INFO, "Using proxy {proxy} for {base_url}");
-                builder.proxy(Proxy::all(proxy)?)
+                builder.proxy(Proxy::all(proxy.clone())?)
             }
             None => {
                 event!(Level::INFO, "Using direct connection for {base_url}");
@@ -292,28 +768,88 @@ impl Mvg {
 
         Ok(Self {
             base_url,
+            proxy,
             client: builder.build()?,
+            direct_client: None,
+        })
+    }
+
+    /// Retry requests once with a direct connection if the proxy fails.
+    ///
+    /// Build the direct client from `builder`; this function sets the user
+    /// agent.  Does nothing if this client connects directly anyway.
+    pub fn with_direct_fallback(self, builder: ClientBuilder) -> Result<Self> {
+        if self.proxy.is_none() {
+            return Ok(self);
+        }
+        let direct_client = builder.user_agent("home").no_proxy().build()?;
+        Ok(Self {
+            direct_client: Some(direct_client),
+            ..self
         })
     }
 
+    /// Send a GET request for JSON to `url`.
+    ///
+    /// If connecting through the proxy fails and we have a direct fallback
+    /// client retry the request once with a direct connection.
+    async fn get_json(&self, url: Url) -> reqwest::Result<Response> {
+        let result = self
+            .client
+            .get(url.clone())
+            .header("Accept", "application/json")
+            .send()
+            .in_current_span()
+            .await;
+        match (result, &self.direct_client) {
+            (Err(error), Some(direct_client)) if error.is_connect() => {
+                event!(
+                    Level::WARN,
+                    "Failed to connect via proxy {}, retrying with direct connection: {error}",
+                    self.proxy_field()
+                );
+                direct_client
+                    .get(url)
+                    .header("Accept", "application/json")
+                    .send()
+                    .in_current_span()
+                    .await
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// The proxy used for the API as field for spans, or "direct".
+    fn proxy_field(&self) -> String {
+        self.proxy
+            .as_ref()
+            .map_or_else(|| "direct".to_string(), |proxy| proxy.to_string())
+    }
+
+    /// Check whether the MVG API is reachable.
+    ///
+    /// Look up a well-known station, and fail if the lookup fails.
+    #[instrument(skip(self))]
+    pub async fn healthcheck(&self) -> Result<()> {
+        self.get_location_by_name("Marienplatz")
+            .in_current_span()
+            .await
+            .map(|_| ())
+            .with_context(|| format!("MVG API at {} not reachable", self.base_url))
+    }
+
     #[instrument(skip(self), fields(name=name.as_ref()))]
     pub async fn get_location_by_name<S: AsRef<str>>(&self, name: S) -> Result<Vec<Location>> {
         event!(Level::INFO, "Finding locations for {}", name.as_ref());
         let mut url = self.base_url.join("location")?;
         url.query_pairs_mut().append_pair("query", name.as_ref());
 
-        let _guard = span!(Level::INFO, "request::GET", %url).entered();
+        let _guard =
+            span!(Level::INFO, "request::GET", %url, proxy = %self.proxy_field()).entered();
         event!(Level::TRACE, %url, "Sending request");
-        let response = self
-            .client
-            .get(url)
-            .header("Accept", "application/json")
-            .send()
-            .in_current_span()
-            .await
-            .with_context(|| {
-                format!("Failed to query URL to resolve location {}", name.as_ref())
-            })?;
+        let response = self.get_json(url).await.with_context(|| {
+            format!("Failed to query URL to resolve location {}", name.as_ref())
+        })?;
         response
             .json::<Vec<LocationOrUnknown>>()
             .in_current_span()
@@ -400,21 +936,97 @@ impl Mvg {
         }
     }
 
-    #[instrument(skip(self), fields(start=%start))]
+    /// Get connections departing at `start`.
     pub async fn get_connections(
         &self,
         origin_station: &Station,
         destination_station: &Station,
         start: DateTime<Utc>,
     ) -> Result<Vec<Connection>> {
+        self.get_connections_with_options(
+            origin_station,
+            destination_station,
+            &RoutingOptions::departing_at(start),
+        )
+        .await
+    }
+
+    /// Get connections between the stations named `start` and `destination`, departing at `time`.
+    ///
+    /// Look up both stations concurrently, see
+    /// [`Self::find_unambiguous_station_by_name`].  Return the connections
+    /// along with the start station.
+    #[instrument(skip(self))]
+    pub async fn get_connections_between_names(
+        &self,
+        start: &str,
+        destination: &str,
+        time: DateTime<Utc>,
+    ) -> Result<(Station, Vec<Connection>)> {
+        let (start, destination) = try_join(
+            self.find_unambiguous_station_by_name(start),
+            self.find_unambiguous_station_by_name(destination),
+        )
+        .await?;
+        event!(
+            Level::DEBUG,
+            "Resolved stations {} and {}",
+            start.global_id(),
+            destination.global_id()
+        );
+        let connections = self.get_connections(&start, &destination, time).await?;
+        Ok((start, connections))
+    }
+
+    /// Get connections according to the given routing `options`.
+    #[instrument(skip(self, options), fields(time=%options.time, is_arrival=options.is_arrival))]
+    pub async fn get_connections_with_options(
+        &self,
+        origin_station: &Station,
+        destination_station: &Station,
+        options: &RoutingOptions,
+    ) -> Result<Vec<Connection>> {
+        let body = self
+            .get_raw_connections_with_options(origin_station, destination_station, options)
+            .in_current_span()
+            .await?;
+        serde_json::from_str::<Vec<Connection>>(&body)
+            .map(|mut connections| {
+                event!(Level::INFO, "Received {} connections", connections.len());
+                if let Some(max_connections) = options.max_connections {
+                    connections.truncate(max_connections);
+                }
+                connections
+            })
+            .with_context(|| {
+                format!(
+                    "Failed to parse response for connection from from {} to {}",
+                    origin_station.global_id, destination_station.global_id
+                )
+            })
+    }
+
+    /// Get the unprocessed JSON body of connections according to the given routing `options`.
+    #[instrument(skip(self, options), fields(time=%options.time, is_arrival=options.is_arrival))]
+    pub async fn get_raw_connections_with_options(
+        &self,
+        origin_station: &Station,
+        destination_station: &Station,
+        options: &RoutingOptions,
+    ) -> Result<String> {
         event!(
             Level::INFO,
-            "Fetching connections between station {} ({}) and station {} ({}) starting at {}",
+            "Fetching connections between station {} ({}) and station {} ({}) {} at {}",
             origin_station.name,
             origin_station.global_id,
             destination_station.name,
             destination_station.global_id,
-            start
+            if options.is_arrival {
+                "arriving"
+            } else {
+                "starting"
+            },
+            options.time
         );
         let mut url = self.base_url.join("connection")?;
         url.query_pairs_mut()
@@ -425,43 +1037,31 @@ impl Mvg {
             )
             .append_pair(
                 "routingDateTime",
-                &start.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                &options
+                    .time
+                    .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
             )
-            .append_pair("routingDateTimeIsArrival", "false")
             .append_pair(
-                "transportTypes",
-                "SCHIFF,RUFTAXI,BAHN,UBAHN,TRAM,SBAHN,BUS,REGIONAL_BUS",
-            );
+                "routingDateTimeIsArrival",
+                if options.is_arrival { "true" } else { "false" },
+            )
+            .append_pair("transportTypes", &options.transport_types_query());
 
-        let _guard = span!(Level::INFO, "request::GET", %url).entered();
+        let _guard =
+            span!(Level::INFO, "request::GET", %url, proxy = %self.proxy_field()).entered();
         event!(Level::TRACE, %url, "Sending request");
-        let response = self
-            .client
-            .get(url)
-            .header("Accept", "application/json")
-            .send()
-            .in_current_span()
-            .await
-            .with_context(|| {
-                format!(
-                    "Failed to query URL to get a connection from {} to {}",
-                    origin_station.global_id, destination_station.global_id
-                )
-            })?;
-        response
-            .json::<Vec<Connection>>()
-            .in_current_span()
-            .await
-            .map(|connections| {
-                event!(Level::INFO, "Received {} connections", connections.len());
-                connections
-            })
-            .with_context(|| {
-                format!(
-                    "Failed to parse response for connection from from {} to {}",
-                    origin_station.global_id, destination_station.global_id
-                )
-            })
+        let response = self.get_json(url).await.with_context(|| {
+            format!(
+                "Failed to query URL to get a connection from {} to {}",
+                origin_station.global_id, destination_station.global_id
+            )
+        })?;
+        response.text().in_current_span().await.with_context(|| {
+            format!(
+                "Failed to read response for connection from {} to {}",
+                origin_station.global_id, destination_station.global_id
+            )
+        })
     }
 }
 
@@ -469,12 +1069,295 @@ impl Mvg {
 mod tests {
     use crate::mvg::*;
     use chrono::{Duration, Timelike};
-    use futures::future::try_join;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn default_routing_transport_types() {
+        assert_eq!(
+            RoutingOptions::departing_at(Utc::now()).transport_types_query(),
+            "SCHIFF,RUFTAXI,BAHN,UBAHN,TRAM,SBAHN,BUS,REGIONAL_BUS"
+        );
+    }
+
+    #[test]
+    fn filtered_routing_transport_types_keep_walking() {
+        let mut options = RoutingOptions {
+            walking: true,
+            ..RoutingOptions::departing_at(Utc::now())
+        };
+        options.transport_types.retain(|t| t.is_rail());
+        assert_eq!(
+            options.transport_types_query(),
+            "BAHN,UBAHN,TRAM,SBAHN,PEDESTRIAN"
+        );
+        // Pedestrian in the transport types neither duplicates nor replaces `walking`
+        options.transport_types.push(TransportType::Pedestrian);
+        assert_eq!(
+            options.transport_types_query(),
+            "BAHN,UBAHN,TRAM,SBAHN,PEDESTRIAN"
+        );
+        options.walking = false;
+        assert_eq!(options.transport_types_query(), "BAHN,UBAHN,TRAM,SBAHN");
+    }
+
+    #[test]
+    fn distance_between_coordinates() {
+        let hauptbahnhof = Coordinates {
+            latitude: 48.14003,
+            longitude: 11.56107,
+        };
+        let ostbahnhof = Coordinates {
+            latitude: 48.12698,
+            longitude: 11.60423,
+        };
+        assert_eq!(hauptbahnhof.distance_to(&hauptbahnhof), 0.0);
+        let distance = hauptbahnhof.distance_to(&ostbahnhof);
+        assert!((3500.0..3520.0).contains(&distance), "{}", distance);
+        assert_eq!(distance, ostbahnhof.distance_to(&hauptbahnhof));
+        let connections = Connection::from_fixture("connections_with_delays");
+        assert_eq!(
+            connections[0].departure().from().coordinates(),
+            Some(hauptbahnhof)
+        );
+    }
+
+    #[test]
+    fn construct_station() {
+        let station = Station::new("de:09162:6", "Hauptbahnhof");
+        assert_eq!(station.global_id(), "de:09162:6");
+        assert_eq!(station.name(), "Hauptbahnhof");
+    }
+
+    #[test]
+    fn parse_transport_types() {
+        for (s, transport_type) in [
+            ("ubahn", TransportType::UBahn),
+            ("u-bahn", TransportType::UBahn),
+            ("UBAHN", TransportType::UBahn),
+            ("S-Bahn", TransportType::SBahn),
+            ("regional-bus", TransportType::RegionalBus),
+            ("REGIONAL_BUS", TransportType::RegionalBus),
+            ("regionalbus", TransportType::RegionalBus),
+            ("Pedestrian", TransportType::Pedestrian),
+        ] {
+            assert_eq!(s.parse::<TransportType>().unwrap(), transport_type);
+        }
+        assert_eq!(
+            "hovercraft"
+                .parse::<TransportType>()
+                .unwrap_err()
+                .to_string(),
+            "Unknown transport type: hovercraft"
+        );
+    }
+
+    #[test]
+    fn display_transport_types() {
+        assert_eq!(TransportType::UBahn.to_string(), "U-Bahn");
+        assert_eq!(TransportType::RegionalBus.to_string(), "Regional-Bus");
+        for transport_type in TransportType::ALL {
+            assert_eq!(
+                transport_type.to_string().parse::<TransportType>().unwrap(),
+                transport_type
+            );
+        }
+    }
+
+    #[test]
+    fn transport_type_groups() {
+        assert!(TransportType::SBahn.is_rail());
+        assert!(TransportType::Tram.is_rail());
+        assert!(!TransportType::Bus.is_rail());
+        assert!(TransportType::RegionalBus.is_bus());
+        assert!(TransportType::Ruftaxi.is_on_demand());
+        assert!(!TransportType::Bus.is_on_demand());
+        assert!(!TransportType::UBahn.is_bus());
+        assert!(!TransportType::Schiff.is_rail());
+        assert!(!TransportType::Schiff.is_bus());
+    }
+
+    #[test]
+    fn connections_with_delays_from_fixture() {
+        let connections = Connection::from_fixture("connections_with_delays");
+        assert_eq!(connections.len(), 2);
+        let transfer = &connections[0];
+        assert_eq!(transfer.transfers(), 1);
+        assert_eq!(transfer.departure_delay(), Some(Duration::zero()));
+        assert_eq!(transfer.arrival_delay(), Some(Duration::minutes(3)));
+        assert_eq!(
+            transfer.actual_arrival_time(),
+            DateTime::parse_from_rfc3339("2024-06-01T18:01:00+02:00").unwrap()
+        );
+        let direct = &connections[1];
+        assert_eq!(direct.transfers(), 0);
+        assert_eq!(
+            direct.departure().line_transport_type(),
+            TransportType::SBahn
+        );
+        assert_eq!(
+            direct.actual_departure_time(),
+            DateTime::parse_from_rfc3339("2024-06-01T17:52:00+02:00").unwrap()
+        );
+    }
+
+    #[test]
+    fn connection_from_parts() {
+        let time = |s| DateTime::parse_from_rfc3339(s).unwrap();
+        let connection = Connection::from_parts(vec![
+            ConnectionPart::new(
+                TransportType::UBahn,
+                "U4",
+                time("2024-06-01T17:42:00+02:00"),
+                time("2024-06-01T17:49:00+02:00"),
+            )
+            .between("Hauptbahnhof", "Max-Weber-Platz"),
+            ConnectionPart::new(
+                TransportType::Pedestrian,
+                "",
+                time("2024-06-01T17:49:00+02:00"),
+                time("2024-06-01T17:52:00+02:00"),
+            )
+            .between("Max-Weber-Platz", "Max-Weber-Platz"),
+            ConnectionPart::new(
+                TransportType::Tram,
+                "19",
+                time("2024-06-01T17:53:00+02:00"),
+                time("2024-06-01T17:58:00+02:00"),
+            )
+            .between("Max-Weber-Platz", "Ostbahnhof")
+            .delayed(3, 4),
+        ]);
+        assert_eq!(connection.departure().from().name(), "Hauptbahnhof");
+        assert_eq!(connection.arrival().to().name(), "Ostbahnhof");
+        assert_eq!(connection.departure_delay(), None);
+        assert_eq!(connection.arrival_delay(), Some(Duration::minutes(4)));
+        assert_eq!(
+            connection.actual_arrival_time(),
+            time("2024-06-01T18:02:00+02:00")
+        );
+        assert_eq!(connection.walking_time(), Duration::minutes(3));
+        assert_eq!(connection.transfers(), 1);
+    }
+
+    #[test]
+    fn delayed_connections_from_fixture() {
+        let connections = Connection::from_fixture("connections_with_delays");
+        // The U4 leaves on time, but the tram at the end arrives late
+        assert!(connections[0].is_delayed());
+        assert!(connections[1].is_delayed());
+        let cancelled = Connection::from_fixture("cancelled_trip");
+        assert!(!cancelled[0].is_delayed());
+    }
+
+    #[test]
+    fn signature_ignores_delays() {
+        let connections = Connection::from_fixture("connections_with_delays");
+        let mut delayed = connections[0].clone();
+        delayed.parts[0].from.departure_delay_in_minutes = Some(7);
+        delayed.parts[1].to.arrival_delay_in_minutes = None;
+        assert_eq!(
+            connections[0].signature(),
+            "2024-06-01T17:42:00+02:00 UBAHN U4 2024-06-01T17:58:00+02:00"
+        );
+        assert_eq!(delayed.signature(), connections[0].signature());
+        assert_ne!(connections[1].signature(), connections[0].signature());
+    }
+
+    #[test]
+    fn walking_time_from_fixture() {
+        let walking = Connection::from_fixture("pedestrian_start");
+        assert_eq!(walking[0].walking_time(), Duration::minutes(4));
+        let riding = Connection::from_fixture("connections_with_delays");
+        assert_eq!(riding[0].walking_time(), Duration::zero());
+    }
+
+    #[test]
+    fn trip_duration() {
+        let departure = DateTime::parse_from_rfc3339("2024-06-01T17:42:00+02:00").unwrap();
+        let connection = Connection::single_part(
+            TransportType::UBahn,
+            "U4",
+            departure,
+            departure + Duration::minutes(23),
+        );
+        assert_eq!(connection.trip_duration(), Duration::minutes(23));
+    }
+
+    #[test]
+    fn planned_arrival_of_stop() {
+        let stop: ConnectionPartStop = serde_json::from_str(
+            r#"{"name": "Ostbahnhof", "plannedDeparture": "2024-06-01T18:19:00+02:00", "plannedArrival": "2024-06-01T18:17:00+02:00"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            stop.planned_arrival(),
+            DateTime::parse_from_rfc3339("2024-06-01T18:17:00+02:00").unwrap()
+        );
+        assert_eq!(
+            stop.planned_departure(),
+            DateTime::parse_from_rfc3339("2024-06-01T18:19:00+02:00").unwrap()
+        );
+
+        let stop: ConnectionPartStop = serde_json::from_str(
+            r#"{"name": "Ostbahnhof", "plannedDeparture": "2024-06-01T18:19:00+02:00"}"#,
+        )
+        .unwrap();
+        assert_eq!(stop.planned_arrival(), stop.planned_departure());
+    }
+
+    #[test]
+    fn cancelled_trip_from_fixture() {
+        let connections = Connection::from_fixture("cancelled_trip");
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].departure_delay(), None);
+        assert_eq!(connections[0].arrival_delay(), None);
+        assert!(connections[0].is_cancelled());
+        let connections = Connection::from_fixture("connections_with_delays");
+        assert!(!connections[0].is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn retry_without_broken_proxy() {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0; 1024];
+            let _ = stream.read(&mut buffer).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]")
+                .unwrap();
+        });
+        let proxy = Url::parse("http://127.0.0.1:1/").unwrap();
+        let mvg = Mvg::with_proxy(ClientBuilder::new(), base_url.clone(), Some(proxy)).unwrap();
+        assert!(mvg
+            .get_json(base_url.clone())
+            .await
+            .unwrap_err()
+            .is_connect());
+        let mvg = mvg.with_direct_fallback(ClientBuilder::new()).unwrap();
+        let response = mvg.get_json(base_url).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "[]");
+    }
+
+    #[tokio::test]
+    async fn unreachable_host_is_network_error() {
+        let error = Client::new()
+            .get("http://127.0.0.1:1/")
+            .send()
+            .await
+            .context("Failed to reach host")
+            .unwrap_err();
+        assert!(is_network_error(&error), "Unexpected error: {:#}", error);
+        assert!(!is_network_error(&anyhow!("Station not found")));
+    }
+
     #[tokio::test]
     async fn big_well_known_station() {
-        let mvg = Mvg::new().await.unwrap();
+        let mvg = Mvg::with_client_builder(ClientBuilder::new())
+            .await
+            .unwrap();
         let name = "Marienplatz";
         let locations = mvg.get_location_by_name(name).await.unwrap();
         assert!(1 < locations.len(), "Too few locations: {:?}", locations);
@@ -489,7 +1372,9 @@ mod tests {
 
     #[tokio::test]
     async fn small_rural_bus_stop() {
-        let mvg = Mvg::new().await.unwrap();
+        let mvg = Mvg::with_client_builder(ClientBuilder::new())
+            .await
+            .unwrap();
         let name = "Fuchswinkl";
         let locations = mvg.get_location_by_name("Fuchswinkl").await.unwrap();
         assert!(!locations.is_empty());
@@ -506,16 +1391,11 @@ mod tests {
     async fn connections_now() {
         // Connections at the current time are supposed to have delay information,
         // so let's use a major connection to test delay information
-        let mvg = Mvg::new().await.unwrap();
-        let (departure, destination) = try_join(
-            mvg.find_unambiguous_station_by_name("München Hbf"),
-            mvg.find_unambiguous_station_by_name("Pasing"),
-        )
-        .await
-        .unwrap();
-
-        let connections = mvg
-            .get_connections(&departure, &destination, Utc::now())
+        let mvg = Mvg::with_client_builder(ClientBuilder::new())
+            .await
+            .unwrap();
+        let (_, connections) = mvg
+            .get_connections_between_names("München Hbf", "Pasing", Utc::now())
             .await
             .unwrap();
         for connection in &connections {
@@ -544,7 +1424,9 @@ mod tests {
 
     #[tokio::test]
     async fn connections() {
-        let mvg = Mvg::new().await.unwrap();
+        let mvg = Mvg::with_client_builder(ClientBuilder::new())
+            .await
+            .unwrap();
         let (departure, destination) = try_join(
             mvg.find_unambiguous_station_by_name("Waldfriedhof"),
             mvg.find_unambiguous_station_by_name("Schwanthaler Höhe"),